@@ -1,20 +1,199 @@
+use std::ops::Range;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
 
 const TARGET_SAMPLE_RATE: u32 = 16000;
 
 const WAVEFORM_SIZE: usize = 2048;
 
-pub fn record_until_stopped(
-    stop: Arc<AtomicBool>,
-    waveform_out: Option<Arc<Mutex<Vec<f32>>>>,
-) -> Result<Vec<f32>, String> {
+/// Don't cut segments shorter than this; Whisper does worse on tiny clips
+const MIN_SEGMENT_SECS: f32 = 8.0;
+/// A pause this long ends a segment
+const PAUSE_SECS: f32 = 0.6;
+/// After a stream fails, how often to try opening a device again
+const RECONNECT_ATTEMPTS: usize = 5;
+const RECONNECT_DELAY: Duration = Duration::from_millis(400);
+/// A stream that delivers nothing for this long counts as failed
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+/// The VAD classifies audio in frames this long
+const VAD_FRAME_SECS: f32 = 0.02;
+/// A frame is speech when this much louder than the noise floor
+const VAD_SPEECH_RATIO: f32 = 3.0;
+/// Speech frames in a row before speech counts as started, so a click or
+/// a bumped desk doesn't
+const VAD_MIN_SPEECH_FRAMES: u32 = 5;
+/// Nothing quieter counts as speech, however quiet the room (about
+/// -54 dBFS)
+const MIN_SPEECH_RMS: f32 = 0.002;
+/// RMS below this counts as silence
+pub const SILENCE_RMS: f32 = 0.01;
+
+/// Device-rate mono audio straight from the input callback, with its rate
+pub type FrameSender = tokio::sync::mpsc::UnboundedSender<(u32, Vec<f32>)>;
+pub type FrameReceiver = tokio::sync::mpsc::UnboundedReceiver<(u32, Vec<f32>)>;
+
+/// An input device as listed by `whisper-paste devices`
+pub struct InputDevice {
+    pub name: String,
+    pub is_default: bool,
+    /// Supported configurations, e.g. "2 ch, 44100-48000 Hz, f32"
+    pub configs: Vec<String>,
+}
+
+pub fn input_devices() -> Result<Vec<InputDevice>, String> {
     let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .ok_or("no input device found")?;
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    let devices = host
+        .input_devices()
+        .map_err(|e| format!("failed to list input devices: {e}"))?;
+
+    Ok(devices
+        .map(|device| {
+            let name = device.name().unwrap_or_else(|_| "(unnamed)".to_string());
+            let configs = device
+                .supported_input_configs()
+                .map(|configs| configs.map(|c| describe_config(&c)).collect())
+                .unwrap_or_default();
+            InputDevice {
+                is_default: default_name.as_ref() == Some(&name),
+                name,
+                configs,
+            }
+        })
+        .collect())
+}
+
+fn describe_config(range: &cpal::SupportedStreamConfigRange) -> String {
+    let (min, max) = (range.min_sample_rate().0, range.max_sample_rate().0);
+    let rates = if min == max {
+        format!("{min} Hz")
+    } else {
+        format!("{min}-{max} Hz")
+    };
+    let channels = range.channels();
+    format!("{channels} ch, {rates}, {}", range.sample_format())
+}
+
+/// Index of the device `wanted` names: an exact match, or else the first
+/// whose name contains it, ignoring case
+fn match_device(names: &[String], wanted: &str) -> Option<usize> {
+    names.iter().position(|n| n == wanted).or_else(|| {
+        let wanted = wanted.to_lowercase();
+        names
+            .iter()
+            .position(|n| n.to_lowercase().contains(&wanted))
+    })
+}
+
+/// The device `wanted` names, as picked by `match_device`
+fn find_input_device(host: &cpal::Host, wanted: &str) -> Option<cpal::Device> {
+    let mut devices: Vec<cpal::Device> = host
+        .input_devices()
+        .map(|devices| devices.collect())
+        .unwrap_or_default();
+    let names: Vec<String> = devices
+        .iter()
+        .map(|d| d.name().unwrap_or_default())
+        .collect();
+    match_device(&names, wanted).map(|i| devices.swap_remove(i))
+}
+
+/// The configured input device, or the system default if it isn't there
+fn open_input_device(host: &cpal::Host, wanted: Option<&str>) -> Result<cpal::Device, String> {
+    if let Some(wanted) = wanted {
+        match find_input_device(host, wanted) {
+            Some(device) => return Ok(device),
+            None => eprintln!(
+                "input device \"{wanted}\" not found, using the default (see `whisper-paste devices`)"
+            ),
+        }
+    }
+    host.default_input_device()
+        .ok_or_else(|| "no input device found".to_string())
+}
+
+/// Energy-based voice activity detection with an adaptive noise floor, fed
+/// from the capture callback. Tells how long it has been quiet since speech.
+#[derive(Default)]
+pub struct Vad {
+    /// The frame being filled
+    frame: Vec<f32>,
+    /// RMS of background noise; `None` until the first frame
+    noise_floor: Option<f32>,
+    speech_frames: u32,
+    heard_speech: bool,
+    /// Seconds since the last speech frame
+    silence: f32,
+}
+
+impl Vad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, mono: &[f32], rate: u32) {
+        let frame_len = ((rate as f32 * VAD_FRAME_SECS) as usize).max(1);
+        for &sample in mono {
+            self.frame.push(sample);
+            if self.frame.len() == frame_len {
+                let level = rms(&self.frame);
+                self.frame.clear();
+                self.classify(level, frame_len as f32 / rate as f32);
+            }
+        }
+    }
+
+    fn classify(&mut self, level: f32, secs: f32) {
+        let floor = *self.noise_floor.get_or_insert(level);
+        if level > speech_threshold(floor) {
+            self.speech_frames += 1;
+            if self.speech_frames >= VAD_MIN_SPEECH_FRAMES {
+                self.heard_speech = true;
+                self.silence = 0.0;
+            } else {
+                self.silence += secs;
+            }
+        } else {
+            self.speech_frames = 0;
+            self.silence += secs;
+            // Drops straight to quieter frames, creeps up under steady noise
+            self.noise_floor = Some(if level < floor {
+                level
+            } else {
+                floor + (level - floor) * 0.05
+            });
+        }
+    }
+
+    /// Seconds of quiet since speech ended; `None` until anyone has spoken
+    pub fn trailing_silence(&self) -> Option<f32> {
+        self.heard_speech.then_some(self.silence)
+    }
+}
+
+/// Where every stream's audio goes besides its own buffer
+struct Outputs {
+    waveform: Option<Arc<Mutex<Vec<f32>>>>,
+    frames: Option<FrameSender>,
+    vad: Option<Arc<Mutex<Vad>>>,
+}
+
+/// A running input stream and the device-rate mono audio it has captured
+struct Capture {
+    _stream: cpal::Stream,
+    device_name: String,
+    rate: u32,
+    samples: Arc<Mutex<Vec<f32>>>,
+    /// Whatever the stream's error callback reports
+    errors: mpsc::Receiver<cpal::StreamError>,
+}
+
+fn open_capture(device: cpal::Device, outputs: &Outputs) -> Result<Capture, String> {
+    let device_name = device.name().unwrap_or_else(|_| "(unnamed)".to_string());
 
     // Use the device's default config instead of forcing our own
     let default_config = device
@@ -32,82 +211,513 @@ pub fn record_until_stopped(
 
     let samples: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
     let samples_clone = samples.clone();
+    let waveform_out = outputs.waveform.clone();
+    let frames_out = outputs.frames.clone();
+    let vad = outputs.vad.clone();
+    let (error_tx, errors) = mpsc::channel();
 
-    let stream = device
-        .build_input_stream(
-            &config,
-            move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                // Mix down to mono if multi-channel
-                let mono: Vec<f32> = if native_channels > 1 {
-                    data.chunks(native_channels as usize)
-                        .map(|frame| frame.iter().sum::<f32>() / native_channels as f32)
-                        .collect()
-                } else {
-                    data.to_vec()
-                };
-                samples_clone.lock().unwrap().extend_from_slice(&mono);
-
-                // Feed waveform display
-                if let Some(ref wf) = waveform_out {
-                    let mut wf = wf.lock().unwrap();
-                    wf.extend_from_slice(&mono);
-                    if wf.len() > WAVEFORM_SIZE {
-                        let excess = wf.len() - WAVEFORM_SIZE;
-                        wf.drain(..excess);
-                    }
-                }
-            },
-            |err| eprintln!("audio stream error: {err}"),
-            None,
-        )
-        .map_err(|e| format!("failed to build input stream: {e}"))?;
+    let on_audio = move |mono: Vec<f32>| {
+        samples_clone.lock().unwrap().extend_from_slice(&mono);
+
+        // Live transcription sessions are fed right from the callback
+        if let Some(ref frames) = frames_out {
+            frames.send((native_rate, mono.clone())).ok();
+        }
+
+        if let Some(ref vad) = vad {
+            vad.lock().unwrap().process(&mono, native_rate);
+        }
+
+        // Feed waveform display
+        if let Some(ref wf) = waveform_out {
+            let mut wf = wf.lock().unwrap();
+            wf.extend_from_slice(&mono);
+            if wf.len() > WAVEFORM_SIZE {
+                let excess = wf.len() - WAVEFORM_SIZE;
+                wf.drain(..excess);
+            }
+        }
+    };
+    let on_error = move |err| {
+        error_tx.send(err).ok();
+    };
+
+    // Capture in whatever format the device delivers natively
+    let stream = match default_config.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, on_audio, on_error),
+        SampleFormat::F64 => build_stream::<f64>(&device, &config, on_audio, on_error),
+        SampleFormat::I8 => build_stream::<i8>(&device, &config, on_audio, on_error),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, on_audio, on_error),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, on_audio, on_error),
+        SampleFormat::I64 => build_stream::<i64>(&device, &config, on_audio, on_error),
+        SampleFormat::U8 => build_stream::<u8>(&device, &config, on_audio, on_error),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, on_audio, on_error),
+        SampleFormat::U32 => build_stream::<u32>(&device, &config, on_audio, on_error),
+        SampleFormat::U64 => build_stream::<u64>(&device, &config, on_audio, on_error),
+        other => return Err(format!("unsupported sample format: {other}")),
+    }
+    .map_err(|e| format!("failed to build input stream: {e}"))?;
 
     stream
         .play()
         .map_err(|e| format!("failed to start stream: {e}"))?;
 
-    while !stop.load(Ordering::SeqCst) {
-        std::thread::sleep(std::time::Duration::from_millis(50));
+    Ok(Capture {
+        _stream: stream,
+        device_name,
+        rate: native_rate,
+        samples,
+        errors,
+    })
+}
+
+/// An input stream whose callback hands `on_audio` each buffer as mono f32
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut on_audio: impl FnMut(Vec<f32>) + Send + 'static,
+    on_error: impl FnMut(cpal::StreamError) + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels;
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| on_audio(to_mono(data, channels)),
+        on_error,
+        None,
+    )
+}
+
+/// Interleaved samples of any format as normalized mono f32 (-1.0..1.0),
+/// averaging the channels of each frame
+fn to_mono<T>(data: &[T], channels: u16) -> Vec<f32>
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    if channels > 1 {
+        data.chunks(channels as usize)
+            .map(|frame| frame.iter().map(|&s| f32::from_sample(s)).sum::<f32>() / channels as f32)
+            .collect()
+    } else {
+        data.iter().map(|&s| f32::from_sample(s)).collect()
     }
+}
 
-    drop(stream);
+/// A new stream after the last one failed: the same device if it can be
+/// opened again, otherwise the default. `None` once every attempt failed or
+/// recording was stopped meanwhile.
+fn reconnect(
+    host: &cpal::Host,
+    device_name: &str,
+    stop: &AtomicBool,
+    outputs: &Outputs,
+) -> Option<Capture> {
+    for attempt in 0..RECONNECT_ATTEMPTS {
+        if attempt > 0 {
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+        if stop.load(Ordering::SeqCst) {
+            return None;
+        }
 
-    let raw = samples.lock().unwrap().clone();
+        let device = find_input_device(host, device_name).or_else(|| host.default_input_device());
+        match device.map(|device| open_capture(device, outputs)) {
+            Some(Ok(capture)) => return Some(capture),
+            Some(Err(e)) => eprintln!("reopening input failed: {e}"),
+            None => eprintln!("reopening input failed: no input device found"),
+        }
+    }
+    None
+}
+
+/// Records until `stop` is set. With `on_segment`, the stream is cut at
+/// natural pauses while recording and each finished segment (16kHz mono) is
+/// handed over right away; the returned samples are then only the tail after
+/// the last cut. `frames_out` gets every callback's audio as it arrives and
+/// is closed when recording ends. `vad`, if given, hears everything too.
+/// `input_device` is matched by `match_device`; `None` means the system
+/// default.
+///
+/// If the stream fails (a headset dropping out, say) it is reopened on the
+/// same device or the default, and `on_warning` is told; if that fails too,
+/// recording ends early with what was captured.
+pub fn record_until_stopped(
+    input_device: Option<&str>,
+    stop: Arc<AtomicBool>,
+    waveform_out: Option<Arc<Mutex<Vec<f32>>>>,
+    frames_out: Option<FrameSender>,
+    vad: Option<Arc<Mutex<Vad>>>,
+    mut on_segment: Option<&mut dyn FnMut(Vec<f32>)>,
+    on_warning: &dyn Fn(&str),
+) -> Result<Vec<f32>, String> {
+    let host = cpal::default_host();
+    let outputs = Outputs {
+        waveform: waveform_out,
+        frames: frames_out,
+        vad,
+    };
+    // The streams go once this block ends, closing `frames_out`
+    let (raw, rate) = {
+        let mut capture = open_capture(open_input_device(&host, input_device)?, &outputs)?;
+
+        // Start of the samples not yet handed to `on_segment`
+        let mut cut_from = 0;
+        // Some backends stop calling back without reporting an error
+        let mut captured = 0;
+        let mut last_audio = Instant::now();
+
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                let raw = capture.samples.lock().unwrap()[cut_from..].to_vec();
+                break (raw, capture.rate);
+            }
+            std::thread::sleep(Duration::from_millis(50));
+
+            let len = capture.samples.lock().unwrap().len();
+            if len > captured {
+                captured = len;
+                last_audio = Instant::now();
+            }
+            let failure = match capture.errors.try_recv() {
+                Ok(err) => Some(err.to_string()),
+                Err(_) if last_audio.elapsed() > STALL_TIMEOUT => Some("no audio".to_string()),
+                Err(_) => None,
+            };
+
+            if let Some(failure) = failure {
+                eprintln!("audio stream error on {}: {failure}", capture.device_name);
+                on_warning("Microphone lost, reconnecting...");
+
+                // Audio not yet cut off carries over to the new stream
+                let pending = capture.samples.lock().unwrap()[cut_from..].to_vec();
+                let (device_name, old_rate) = (capture.device_name.clone(), capture.rate);
+                drop(capture);
 
-    // Resample to 16kHz if needed
-    let resampled = if native_rate != TARGET_SAMPLE_RATE {
-        resample(&raw, native_rate, TARGET_SAMPLE_RATE)
+                match reconnect(&host, &device_name, &stop, &outputs) {
+                    Some(new) => {
+                        if new.device_name == device_name {
+                            on_warning("Microphone reconnected");
+                        } else {
+                            println!("Recording from {} instead", new.device_name);
+                            on_warning("Switched to the default microphone");
+                        }
+                        let pending = resample(&pending, old_rate, new.rate);
+                        new.samples.lock().unwrap().splice(0..0, pending);
+                        capture = new;
+                        cut_from = 0;
+                        captured = 0;
+                        last_audio = Instant::now();
+                    }
+                    // `reconnect` also gives up when the user stops recording
+                    None if stop.load(Ordering::SeqCst) => break (pending, old_rate),
+                    None => {
+                        eprintln!("no input device could be reopened; keeping what was recorded");
+                        break (pending, old_rate);
+                    }
+                }
+                continue;
+            }
+
+            if let Some(ref mut on_segment) = on_segment {
+                let segment = {
+                    let buf = capture.samples.lock().unwrap();
+                    find_pause_cut(&buf[cut_from..], capture.rate)
+                        .map(|cut| buf[cut_from..cut_from + cut].to_vec())
+                };
+                if let Some(segment) = segment {
+                    cut_from += segment.len();
+                    on_segment(to_target_rate(&segment, capture.rate));
+                }
+            }
+        }
+    };
+    drop(outputs);
+
+    Ok(to_target_rate(&raw, rate))
+}
+
+/// Resample to 16kHz if needed
+fn to_target_rate(samples: &[f32], native_rate: u32) -> Vec<f32> {
+    if native_rate != TARGET_SAMPLE_RATE {
+        resample(samples, native_rate, TARGET_SAMPLE_RATE)
     } else {
-        raw
+        samples.to_vec()
+    }
+}
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// The part of `samples` (16kHz mono) from the start of the first stretch
+/// of speech to the end of the last, widened by `padding` seconds each side.
+/// `None` if nobody spoke. Speech is judged against the recording's own
+/// noise floor, so a quiet microphone works as well as a loud one. Sounds
+/// shorter than the VAD's minimum, like the hotkey's click, don't count.
+pub fn speech_bounds(samples: &[f32], padding: f32) -> Option<Range<usize>> {
+    let frame_len = (TARGET_SAMPLE_RATE as f32 * VAD_FRAME_SECS) as usize;
+    let levels: Vec<f32> = samples.chunks(frame_len).map(rms).collect();
+    let floor = noise_floor(&levels);
+    let threshold = speech_threshold(floor);
+    let loud: Vec<bool> = levels.iter().map(|&level| level >= threshold).collect();
+
+    // First and last frame (exclusive) of long enough runs of loud frames
+    let mut speech: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < loud.len() {
+        let start = i;
+        while i < loud.len() && loud[i] {
+            i += 1;
+        }
+        if i - start >= VAD_MIN_SPEECH_FRAMES as usize {
+            speech = Some((speech.map_or(start, |(first, _)| first), i));
+        }
+        i += 1;
+    }
+
+    let Some((first, last)) = speech else {
+        // Nothing stands out from a loud background, e.g. talking the whole
+        // time: better to send it all than to drop it
+        return (floor >= SILENCE_RMS).then_some(0..samples.len());
     };
+    let pad = (padding * TARGET_SAMPLE_RATE as f32) as usize;
+    let start = (first * frame_len).saturating_sub(pad);
+    let end = (last * frame_len + pad).min(samples.len());
+    Some(start..end)
+}
+
+/// Background level of a recording: the average of its quietest frames
+fn noise_floor(levels: &[f32]) -> f32 {
+    let mut sorted = levels.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let quietest = &sorted[..(sorted.len() / 20).max(1).min(sorted.len())];
+    quietest.iter().sum::<f32>() / quietest.len().max(1) as f32
+}
+
+/// Frames at least this loud count as speech over a background of `floor`
+fn speech_threshold(floor: f32) -> f32 {
+    (floor * VAD_SPEECH_RATIO).max(MIN_SPEECH_RMS)
+}
+
+/// Where to cut `pending` (the samples since the last cut), if it is long
+/// enough and currently ends in a pause. Like `speech_bounds`, a pause is
+/// judged against the noise floor of `pending`, so quiet microphones don't
+/// look paused all the time. The cut lands in the middle of the pause so
+/// both segments keep a little silence around the speech.
+pub fn find_pause_cut(pending: &[f32], rate: u32) -> Option<usize> {
+    let min_len = (rate as f32 * MIN_SEGMENT_SECS) as usize;
+    let pause_len = (rate as f32 * PAUSE_SECS) as usize;
+    if pending.len() < min_len.max(pause_len) {
+        return None;
+    }
 
-    Ok(resampled)
+    let frame_len = ((rate as f32 * VAD_FRAME_SECS) as usize).max(1);
+    let levels: Vec<f32> = pending.chunks(frame_len).map(rms).collect();
+    let floor = noise_floor(&levels);
+    let threshold = speech_threshold(floor);
+    let tail = &pending[pending.len() - pause_len..];
+    let paused = if levels.iter().any(|&level| level >= threshold) {
+        rms(tail) < threshold
+    } else {
+        // Nothing stands out from the background: a pause if that is quiet,
+        // talking the whole time if not
+        floor < SILENCE_RMS
+    };
+    paused.then_some(pending.len() - pause_len / 2)
 }
 
-/// Simple linear interpolation resampler
-fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+/// Windowed-sinc resampler for any pair of rates. A low-pass at the lower
+/// of the two Nyquist frequencies keeps noise above it from aliasing into
+/// the speech band. Samples past either end repeat the edge sample.
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
     if samples.is_empty() || from_rate == to_rate {
         return samples.to_vec();
     }
+    let mut resampler = Resampler::new(from_rate, to_rate);
+    let mut out = resampler.process(samples);
+    out.extend(resampler.finish());
+    out
+}
 
-    let ratio = from_rate as f64 / to_rate as f64;
-    let out_len = (samples.len() as f64 / ratio) as usize;
-    let mut out = Vec::with_capacity(out_len);
+/// `resample` for audio that arrives in blocks. The filter history and
+/// phase carry over from one block to the next, so the output is the same
+/// as resampling the whole stream at once.
+pub struct Resampler {
+    /// Output sample i sits at source position i * step / up
+    up: u64,
+    step: u64,
+    filter: SincFilter,
+    /// Source samples later outputs still need
+    pending: Vec<f32>,
+    /// Source index of `pending[0]`; negative while it holds the copies of
+    /// the first sample that stand in for audio before the start
+    offset: i64,
+    /// Source samples received so far
+    received: u64,
+    /// Index of the next output sample
+    next: u64,
+}
 
-    for i in 0..out_len {
-        let src_idx = i as f64 * ratio;
-        let idx = src_idx as usize;
-        let frac = src_idx - idx as f64;
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        let g = gcd(from_rate, to_rate) as u64;
+        let (up, step) = (to_rate as u64 / g, from_rate as u64 / g);
+        Self {
+            up,
+            step,
+            filter: SincFilter::new(from_rate, to_rate, up.min(MAX_PHASES) as usize),
+            pending: Vec::new(),
+            offset: 0,
+            received: 0,
+            next: 0,
+        }
+    }
 
-        let s = if idx + 1 < samples.len() {
-            samples[idx] * (1.0 - frac as f32) + samples[idx + 1] * frac as f32
+    /// Resampled audio for as far as `samples` (and what came before) reach;
+    /// the last few outputs wait for the next block or `finish`
+    pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+        if samples.is_empty() {
+            return Vec::new();
+        }
+        if self.received == 0 {
+            let reach = self.filter.half_taps - 1;
+            self.pending.extend(std::iter::repeat_n(samples[0], reach));
+            self.offset = -(reach as i64);
+        }
+        self.pending.extend_from_slice(samples);
+        self.received += samples.len() as u64;
+        self.drain(u64::MAX)
+    }
+
+    /// The outputs held back at the end of the stream, with the last sample
+    /// repeated past it
+    pub fn finish(&mut self) -> Vec<f32> {
+        let Some(&last) = self.pending.last() else {
+            return Vec::new();
+        };
+        self.pending
+            .extend(std::iter::repeat_n(last, self.filter.half_taps + 2));
+        self.drain(self.received * self.up / self.step)
+    }
+
+    /// (first source sample, kernel) for output `i`
+    fn position(&self, i: u64) -> (i64, usize) {
+        let base = (i * self.step / self.up) as i64;
+        let frac = (i * self.step % self.up) as f64 / self.up as f64;
+        // The nearest precomputed phase; exact unless `up` is huge
+        let phase = (frac * self.filter.phases as f64).round() as usize;
+        let (base, phase) = if phase == self.filter.phases {
+            (base + 1, 0)
         } else {
-            samples[idx.min(samples.len() - 1)]
+            (base, phase)
         };
-        out.push(s);
+        (base - (self.filter.half_taps as i64 - 1), phase)
     }
 
-    out
+    /// Outputs up to `limit` whose input is all there
+    fn drain(&mut self, limit: u64) -> Vec<f32> {
+        let mut out = Vec::new();
+        let available = self.offset + self.pending.len() as i64;
+        while self.next < limit {
+            let (start, phase) = self.position(self.next);
+            let kernel = self.filter.phase(phase);
+            if start + kernel.len() as i64 > available {
+                break;
+            }
+            let from = (start - self.offset) as usize;
+            let window = &self.pending[from..from + kernel.len()];
+            out.push(window.iter().zip(kernel).map(|(s, k)| s * k).sum());
+            self.next += 1;
+        }
+
+        // Forget input no later output reaches back to
+        let (start, _) = self.position(self.next);
+        let used = (start - self.offset).clamp(0, self.pending.len() as i64);
+        self.pending.drain(..used as usize);
+        self.offset += used;
+        out
+    }
+}
+
+/// Zero crossings of the sinc on each side of the centre; more gives a
+/// steeper cutoff at the cost of more taps
+const SINC_ZERO_CROSSINGS: f64 = 24.0;
+/// Cutoff as a fraction of the lower Nyquist frequency, leaving room for the
+/// transition band below it
+const SINC_ROLLOFF: f64 = 0.9;
+/// Fractional positions get rounded to this many precomputed kernels when
+/// the rates don't reduce to fewer
+const MAX_PHASES: u64 = 1024;
+
+/// Blackman-windowed sinc kernels, one per fractional source position
+struct SincFilter {
+    /// Taps either side of the source sample at or before the output position
+    half_taps: usize,
+    phases: usize,
+    /// `phases` kernels of `2 * half_taps` coefficients, back to back
+    coeffs: Vec<f32>,
+}
+
+impl SincFilter {
+    fn new(from_rate: u32, to_rate: u32, phases: usize) -> Self {
+        // In cycles per source sample
+        let cutoff = 0.5 * SINC_ROLLOFF * (to_rate as f64 / from_rate as f64).min(1.0);
+        let half_width = SINC_ZERO_CROSSINGS / (2.0 * cutoff);
+        let half_taps = half_width.ceil() as usize;
+        let taps = 2 * half_taps;
+
+        let mut coeffs = Vec::with_capacity(phases * taps);
+        for phase in 0..phases {
+            let frac = phase as f64 / phases as f64;
+            let kernel: Vec<f64> = (0..taps)
+                .map(|k| {
+                    // Distance from the output position, in source samples
+                    let x = k as f64 - (half_taps as f64 - 1.0) - frac;
+                    if x.abs() >= half_width {
+                        return 0.0;
+                    }
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        let a = 2.0 * std::f64::consts::PI * cutoff * x;
+                        a.sin() / a
+                    };
+                    let w = std::f64::consts::PI * x / half_width;
+                    sinc * (0.42 + 0.5 * w.cos() + 0.08 * (2.0 * w).cos())
+                })
+                .collect();
+            // Unity gain at DC for every phase
+            let sum: f64 = kernel.iter().sum();
+            coeffs.extend(kernel.iter().map(|c| (c / sum) as f32));
+        }
+
+        Self {
+            half_taps,
+            phases,
+            coeffs,
+        }
+    }
+
+    fn phase(&self, phase: usize) -> &[f32] {
+        let taps = 2 * self.half_taps;
+        &self.coeffs[phase * taps..(phase + 1) * taps]
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 pub fn samples_to_wav(samples: &[f32]) -> Vec<u8> {
@@ -129,6 +739,27 @@ pub fn samples_to_wav(samples: &[f32]) -> Vec<u8> {
     buf.into_inner()
 }
 
+/// Inverse of `samples_to_wav`, for audio read back from disk
+pub fn wav_to_samples(wav: &[u8]) -> Result<Vec<f32>, String> {
+    let reader = hound::WavReader::new(std::io::Cursor::new(wav))
+        .map_err(|e| format!("invalid WAV: {e}"))?;
+    let spec = reader.spec();
+    if spec.channels != 1 || spec.sample_rate != TARGET_SAMPLE_RATE || spec.bits_per_sample != 16 {
+        return Err(format!(
+            "unsupported WAV format: {} ch, {} Hz, {} bit",
+            spec.channels, spec.sample_rate, spec.bits_per_sample
+        ));
+    }
+
+    reader
+        .into_samples::<i16>()
+        .map(|s| {
+            s.map(|v| v as f32 / i16::MAX as f32)
+                .map_err(|e| format!("invalid WAV: {e}"))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +802,16 @@ mod tests {
         assert_eq!(reader.len() as usize, n);
     }
 
+    #[test]
+    fn wav_round_trip() {
+        let samples = vec![0.0, 0.25, -0.5, 0.999];
+        let back = wav_to_samples(&samples_to_wav(&samples)).unwrap();
+        assert_eq!(back.len(), samples.len());
+        for (a, b) in samples.iter().zip(&back) {
+            assert!((a - b).abs() < 1e-3);
+        }
+    }
+
     #[test]
     fn resample_same_rate_is_identity() {
         let input = vec![1.0, 2.0, 3.0, 4.0];
@@ -187,9 +828,289 @@ mod tests {
         assert!((output.len() as i32 - expected_len as i32).abs() <= 1);
     }
 
+    fn tone(secs: f32, rate: u32) -> Vec<f32> {
+        (0..(secs * rate as f32) as usize)
+            .map(|i| (i as f32 * 0.05).sin() * 0.3)
+            .collect()
+    }
+
+    #[test]
+    fn pause_cut_needs_minimum_length() {
+        let mut pending = tone(2.0, 16000);
+        pending.extend(vec![0.0; 16000]);
+        assert_eq!(find_pause_cut(&pending, 16000), None);
+    }
+
+    #[test]
+    fn pause_cut_lands_inside_trailing_pause() {
+        let mut pending = tone(MIN_SEGMENT_SECS + 1.0, 48000);
+        let speech_len = pending.len();
+        pending.extend(vec![0.0; 48000]);
+
+        let cut = find_pause_cut(&pending, 48000).unwrap();
+        assert!(cut > speech_len && cut < pending.len());
+    }
+
+    #[test]
+    fn no_pause_cut_while_speaking() {
+        let pending = tone(MIN_SEGMENT_SECS + 2.0, 16000);
+        assert_eq!(find_pause_cut(&pending, 16000), None);
+    }
+
+    #[test]
+    fn quiet_speech_is_not_a_pause() {
+        let quiet = |secs| -> Vec<f32> { tone(secs, 16000).iter().map(|s| s * 0.02).collect() };
+        let mut pending = hiss(0.5, 0.0005);
+        pending.extend(quiet(MIN_SEGMENT_SECS + 1.0));
+        assert_eq!(find_pause_cut(&pending, 16000), None);
+
+        pending.extend(hiss(1.0, 0.0005));
+        let cut = find_pause_cut(&pending, 16000).unwrap();
+        assert!(cut > pending.len() - 16000);
+    }
+
+    #[test]
+    fn vad_waits_for_speech() {
+        let mut vad = Vad::new();
+        vad.process(&vec![0.0; 48000], 16000);
+        assert_eq!(vad.trailing_silence(), None);
+
+        // A click is too short to count
+        vad.process(&tone(0.04, 16000), 16000);
+        vad.process(&vec![0.0; 16000], 16000);
+        assert_eq!(vad.trailing_silence(), None);
+    }
+
+    #[test]
+    fn vad_counts_silence_after_speech() {
+        let mut vad = Vad::new();
+        vad.process(&vec![0.0; 8000], 16000);
+        vad.process(&tone(1.0, 16000), 16000);
+        assert_eq!(vad.trailing_silence(), Some(0.0));
+
+        vad.process(&vec![0.0; 24000], 16000);
+        let silence = vad.trailing_silence().unwrap();
+        assert!((silence - 1.5).abs() < 0.05, "{silence}");
+
+        // Speaking again starts the countdown over
+        vad.process(&tone(0.5, 16000), 16000);
+        assert_eq!(vad.trailing_silence(), Some(0.0));
+    }
+
+    #[test]
+    fn vad_hears_quiet_microphones() {
+        let mut vad = Vad::new();
+        vad.process(&hiss(1.0, 0.0005), 16000);
+        let quiet: Vec<f32> = tone(1.0, 16000).iter().map(|s| s * 0.02).collect();
+        vad.process(&quiet, 16000);
+        vad.process(&hiss(0.5, 0.0005), 16000);
+        let silence = vad.trailing_silence().unwrap();
+        assert!((silence - 0.5).abs() < 0.05, "{silence}");
+    }
+
+    #[test]
+    fn vad_hears_speech_over_steady_noise() {
+        let hum = |secs: f32| -> Vec<f32> {
+            (0..(secs * 48000.0) as usize)
+                .map(|i| (i as f32 * 0.0131).sin() * 0.03)
+                .collect()
+        };
+        let mut vad = Vad::new();
+        vad.process(&hum(2.0), 48000);
+        assert_eq!(vad.trailing_silence(), None);
+
+        let speech: Vec<f32> = tone(1.0, 48000)
+            .iter()
+            .zip(hum(1.0))
+            .map(|(s, h)| s + h)
+            .collect();
+        vad.process(&speech, 48000);
+        vad.process(&hum(1.0), 48000);
+        let silence = vad.trailing_silence().unwrap();
+        assert!((silence - 1.0).abs() < 0.05, "{silence}");
+    }
+
+    #[test]
+    fn silence_is_trimmed_down_to_padding() {
+        let mut samples = vec![0.0; 16000];
+        samples.extend(tone(2.0, 16000));
+        samples.extend(vec![0.0; 24000]);
+
+        let speech = speech_bounds(&samples, 0.25).unwrap();
+        assert_eq!(speech, 12000..52000);
+        assert!(speech_bounds(&samples, 0.0).unwrap().len() == 32000);
+
+        // Padding stops at the ends of the recording
+        assert_eq!(speech_bounds(&samples, 5.0).unwrap(), 0..samples.len());
+    }
+
+    #[test]
+    fn clicks_and_silence_are_no_speech() {
+        assert_eq!(speech_bounds(&[], 0.25), None);
+        assert_eq!(speech_bounds(&vec![0.0; 32000], 0.25), None);
+
+        let mut click = vec![0.0; 8000];
+        click.extend(tone(0.04, 16000));
+        click.extend(vec![0.0; 8000]);
+        assert_eq!(speech_bounds(&click, 0.25), None);
+
+        // ...but a click before speech doesn't move the start
+        click.extend(tone(1.0, 16000));
+        let speech = speech_bounds(&click, 0.0).unwrap();
+        assert_eq!(speech.start, 16640);
+    }
+
+    /// Low-level white noise, like a quiet room through a low-gain mic
+    fn hiss(secs: f32, level: f32) -> Vec<f32> {
+        let mut x = 0x2545_f491u32;
+        (0..(secs * 16000.0) as usize)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                (x as f32 / u32::MAX as f32 * 2.0 - 1.0) * level
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quiet_speech_is_kept() {
+        // Speech peaking at 0.006, well under SILENCE_RMS
+        let mut samples = hiss(1.0, 0.0005);
+        samples.extend(tone(1.0, 16000).iter().map(|s| s * 0.02));
+        samples.extend(hiss(1.0, 0.0005));
+
+        let speech = speech_bounds(&samples, 0.0).unwrap();
+        assert!(speech.start.abs_diff(16000) <= 320, "{speech:?}");
+        assert!(speech.end.abs_diff(32000) <= 320, "{speech:?}");
+
+        assert_eq!(speech_bounds(&hiss(3.0, 0.0005), 0.25), None);
+    }
+
+    #[test]
+    fn steady_speech_is_sent_whole() {
+        let samples = tone(2.0, 16000);
+        assert_eq!(speech_bounds(&samples, 0.25), Some(0..samples.len()));
+    }
+
+    #[test]
+    fn device_names_match_exactly_then_by_substring() {
+        let names: Vec<String> = ["HD Webcam C920", "Headset Microphone (Jabra)", "Headset"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        assert_eq!(match_device(&names, "Headset"), Some(2));
+        assert_eq!(match_device(&names, "jabra"), Some(1));
+        assert_eq!(match_device(&names, "webcam"), Some(0));
+        assert_eq!(match_device(&names, "USB"), None);
+    }
+
+    #[test]
+    fn integer_formats_are_normalized() {
+        assert_eq!(to_mono(&[0i16, i16::MIN, 16384], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[32768u16, 0, 49152], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[0i32, i32::MIN, 1 << 30], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[0i8, i8::MIN, 64], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[128u8, 0, 192], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[0i64, i64::MIN, 1 << 62], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[1u32 << 31, 0, 3 << 30], 1), [0.0, -1.0, 0.5]);
+        assert_eq!(to_mono(&[1u64 << 63, 0, 3 << 62], 1), [0.0, -1.0, 0.5]);
+
+        // Full scale positive falls just short of 1.0
+        assert!((to_mono(&[i16::MAX], 1)[0] - 1.0).abs() < 1e-4);
+        assert!((to_mono(&[u16::MAX], 1)[0] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn float_formats_pass_through() {
+        assert_eq!(to_mono(&[0.25f32, -0.75], 1), [0.25, -0.75]);
+        assert_eq!(to_mono(&[0.25f64, -0.75], 1), [0.25, -0.75]);
+    }
+
+    #[test]
+    fn channels_are_mixed_down_after_conversion() {
+        // Two stereo frames: full left only, then opposite halves
+        let stereo = [i16::MIN, 0, 16384, -16384];
+        assert_eq!(to_mono(&stereo, 2), [-0.5, 0.0]);
+        assert_eq!(to_mono(&[32768u16, 0, 32768, 0], 2), [-0.5, -0.5]);
+    }
+
     #[test]
     fn resample_empty_input() {
         let output = resample(&[], 48000, 16000);
         assert!(output.is_empty());
     }
+
+    fn sine(freq: f64, secs: f64, rate: u32) -> Vec<f32> {
+        (0..(secs * rate as f64) as usize)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32)
+            .collect()
+    }
+
+    /// Amplitude of a resampled sine, away from the edges
+    fn gain(freq: f64, from_rate: u32, to_rate: u32) -> f32 {
+        let out = resample(&sine(freq, 0.5, from_rate), from_rate, to_rate);
+        let middle = &out[out.len() / 4..out.len() * 3 / 4];
+        rms(middle) * std::f32::consts::SQRT_2
+    }
+
+    #[test]
+    fn resampling_in_blocks_matches_one_pass() {
+        let input = sine(1000.0, 0.5, 44100);
+        let whole = resample(&input, 44100, 16000);
+        for block in [1000, 4410, 7] {
+            let mut resampler = Resampler::new(44100, 16000);
+            let mut out: Vec<f32> = input
+                .chunks(block)
+                .flat_map(|chunk| resampler.process(chunk))
+                .collect();
+            out.extend(resampler.finish());
+            assert_eq!(out, whole, "blocks of {block}");
+        }
+    }
+
+    #[test]
+    fn resample_lengths_follow_the_ratio() {
+        for (from, to) in [
+            (48000, 16000),
+            (44100, 16000),
+            (22050, 16000),
+            (8000, 16000),
+        ] {
+            for len in [1, 441, 16001, 44100] {
+                let expected = len as f64 * to as f64 / from as f64;
+                let out = resample(&vec![0.5; len], from, to);
+                assert!(
+                    (out.len() as f64 - expected).abs() < 1.0,
+                    "{from} -> {to}, {len} samples: got {}",
+                    out.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resample_keeps_the_speech_band() {
+        for from in [48000, 44100, 22050] {
+            for freq in [100.0, 1000.0, 4000.0, 6000.0] {
+                let g = gain(freq, from, 16000);
+                assert!((g - 1.0).abs() < 0.01, "{freq} Hz from {from}: gain {g}");
+            }
+        }
+        assert!((gain(1000.0, 16000, 48000) - 1.0).abs() < 0.02);
+
+        // DC stays put, edges included
+        assert!(resample(&vec![0.5; 4410], 44100, 16000)
+            .iter()
+            .all(|s| (s - 0.5).abs() < 1e-4));
+    }
+
+    #[test]
+    fn resample_filters_out_what_would_alias() {
+        // Above 8 kHz, these would fold back to 4 kHz, 6 kHz and 2.1 kHz
+        for (freq, from) in [(12000.0, 48000), (10000.0, 48000), (13900.0, 44100)] {
+            let g = gain(freq, from, 16000);
+            assert!(g < 1e-3, "{freq} Hz from {from}: gain {g}");
+        }
+    }
 }
@@ -0,0 +1,253 @@
+//! Splitting recordings that are too long for one upload. Chunks are cut at
+//! the quietest point before the limit and overlap a little, so a word cut
+//! in half is still heard whole in one of them; the repeated words are
+//! removed again when the texts are stitched.
+
+use std::ops::Range;
+
+use crate::audio;
+use crate::transcribe::{self, Partial, Task, TranscribeError, Transcript, TranscriptionBackend};
+
+const SAMPLE_RATE: f32 = 16000.0;
+/// Audio shared by neighbouring chunks when the cut is not in a pause
+const OVERLAP_SECS: f32 = 2.0;
+/// How far back from the limit to look for a pause to cut at
+const SEARCH_SECS: f32 = 30.0;
+/// Window for finding the quietest cut point
+const WINDOW_SECS: f32 = 0.1;
+/// Longest run of repeated words looked for at a chunk boundary
+const MAX_OVERLAP_WORDS: usize = 12;
+
+/// Called with (chunk number, total) before each chunk of a split recording
+pub type Progress<'a> = &'a (dyn Fn(usize, usize) + Send + Sync);
+
+/// Like `transcribe::transcribe_with_retry`, but recordings longer than the
+/// backend accepts are sent as consecutive chunks and stitched
+pub async fn transcribe_chunked(
+    backend: &dyn TranscriptionBackend,
+    task: Task,
+    samples: &[f32],
+    progress: Progress<'_>,
+    partial: Partial<'_>,
+) -> Result<Transcript, TranscribeError> {
+    let ranges = backend
+        .max_samples()
+        .map(|max_len| plan(samples, max_len))
+        .unwrap_or_default();
+    if ranges.len() <= 1 {
+        return transcribe::transcribe_with_retry(backend, task, samples, partial).await;
+    }
+
+    let mut parts = Vec::with_capacity(ranges.len());
+    for (i, range) in ranges.iter().enumerate() {
+        progress(i + 1, ranges.len());
+        let part =
+            transcribe::transcribe_with_retry(backend, task, &samples[range.clone()], partial)
+                .await?;
+        parts.push((range.clone(), part));
+    }
+    Ok(stitch(parts))
+}
+
+/// Chunk ranges no longer than `max_len`. Each cut lands in the quietest
+/// 100ms of the last `SEARCH_SECS` before the limit, the latest one on a
+/// tie; unless that is silent, the next chunk starts `OVERLAP_SECS` earlier.
+fn plan(samples: &[f32], max_len: usize) -> Vec<Range<usize>> {
+    let window = (SAMPLE_RATE * WINDOW_SECS) as usize;
+    let overlap = ((SAMPLE_RATE * OVERLAP_SECS) as usize).min(max_len / 4);
+    let search = ((SAMPLE_RATE * SEARCH_SECS) as usize).min(max_len / 2);
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while samples.len() - start > max_len {
+        let limit = start + max_len;
+        let (cut, quiet) = (limit - search..limit - window)
+            .step_by(window / 2)
+            .rev()
+            .map(|pos| (pos, audio::rms(&samples[pos..pos + window])))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(pos, rms)| (pos + window / 2, rms < audio::SILENCE_RMS))
+            .unwrap_or((limit, false));
+
+        ranges.push(start..cut);
+        start = if quiet { cut } else { cut - overlap };
+    }
+    ranges.push(start..samples.len());
+    ranges
+}
+
+/// Joins chunk results into one transcript. Where chunks overlap, segments
+/// and words are taken from the earlier chunk before the middle of the
+/// overlap and from the later one after it.
+fn stitch(parts: Vec<(Range<usize>, Transcript)>) -> Transcript {
+    let mut joined = Transcript::default();
+    let mut prev_end: Option<usize> = None;
+
+    for (range, part) in parts {
+        let offset = range.start as f32 / SAMPLE_RATE;
+        let boundary = match prev_end {
+            Some(end) if end > range.start => (range.start + end) as f32 / 2.0 / SAMPLE_RATE,
+            _ => offset,
+        };
+        prev_end = Some(range.end);
+
+        joined.text = merge_text(&joined.text, part.text.trim());
+        if joined.language.is_none() {
+            joined.language = part.language;
+        }
+        if joined.fallback.is_none() {
+            joined.fallback = part.fallback;
+        }
+        if let Some(duration) = part.duration {
+            joined.duration = Some(offset + duration);
+        }
+
+        joined.segments.retain(|s| s.start < boundary);
+        joined
+            .segments
+            .extend(part.segments.into_iter().filter_map(|mut s| {
+                s.start += offset;
+                s.end += offset;
+                (s.start >= boundary).then_some(s)
+            }));
+        joined.words.retain(|w| w.start < boundary);
+        joined
+            .words
+            .extend(part.words.into_iter().filter_map(|mut w| {
+                w.start += offset;
+                w.end += offset;
+                (w.start >= boundary).then_some(w)
+            }));
+    }
+
+    joined
+}
+
+/// Word comparison key, ignoring case and punctuation
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Appends `next` to `prev`, dropping the words at the start of `next` that
+/// repeat the end of `prev`. Up to two garbled words at the very start of
+/// `next` (half a word at the cut) are dropped along with a repeat.
+fn merge_text(prev: &str, next: &str) -> String {
+    if prev.is_empty() || next.is_empty() {
+        return format!("{prev}{next}");
+    }
+
+    let prev_words: Vec<String> = prev.split_whitespace().map(normalize).collect();
+    let next_words: Vec<&str> = next.split_whitespace().collect();
+    let next_keys: Vec<String> = next_words.iter().map(|w| normalize(w)).collect();
+
+    let max_k = MAX_OVERLAP_WORDS
+        .min(prev_words.len())
+        .min(next_words.len());
+    let mut drop = 0;
+    'search: for k in (1..=max_k).rev() {
+        let tail = &prev_words[prev_words.len() - k..];
+        for skip in 0..=2 {
+            // A single repeated word after skipping some is too weak a match
+            if skip > 0 && k < 2 || skip + k > next_keys.len() {
+                continue;
+            }
+            if tail == &next_keys[skip..skip + k] {
+                drop = skip + k;
+                break 'search;
+            }
+        }
+    }
+
+    let rest = next_words[drop..].join(" ");
+    if rest.is_empty() {
+        prev.to_string()
+    } else {
+        format!("{prev} {rest}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcribe::Word;
+
+    #[test]
+    fn short_recordings_are_one_chunk() {
+        let ranges = plan(&[0.1; 1000], 1000);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0], 0..1000);
+    }
+
+    #[test]
+    fn chunks_overlap_unless_cut_in_silence() {
+        let max_len = 16000 * 60;
+        let loud = vec![0.3f32; 16000 * 150];
+        let ranges = plan(&loud, max_len);
+        assert_eq!(ranges.len(), 3);
+        assert!(ranges.iter().all(|r| r.len() <= max_len));
+        assert_eq!(ranges[0].end - ranges[1].start, 32000);
+        assert_eq!(ranges.last().unwrap().end, loud.len());
+
+        // A pause 10s before the limit is where the cut goes
+        let mut paused = loud.clone();
+        paused[16000 * 50..16000 * 51].fill(0.0);
+        let ranges = plan(&paused, max_len);
+        assert!((16000 * 50..16000 * 51).contains(&ranges[0].end));
+        assert_eq!(ranges[0].end, ranges[1].start);
+    }
+
+    #[test]
+    fn repeated_overlap_words_are_dropped() {
+        assert_eq!(
+            merge_text(
+                "we should ship it on Friday.",
+                "on friday, after the review"
+            ),
+            "we should ship it on Friday. after the review"
+        );
+        assert_eq!(
+            merge_text("the quarterly numbers look", "erly numbers look good"),
+            "the quarterly numbers look good"
+        );
+        assert_eq!(
+            merge_text("no overlap here", "at all"),
+            "no overlap here at all"
+        );
+        assert_eq!(merge_text("", "first"), "first");
+    }
+
+    #[test]
+    fn stitch_takes_words_from_each_side_of_the_overlap() {
+        let word = |w: &str, start: f32| Word {
+            word: w.to_string(),
+            start,
+            end: start + 0.4,
+        };
+        let first = Transcript {
+            text: "one two three".to_string(),
+            words: vec![word("one", 0.5), word("two", 8.5), word("three", 9.5)],
+            ..Default::default()
+        };
+        let second = Transcript {
+            text: "two three four".to_string(),
+            words: vec![word("two", 0.5), word("three", 1.5), word("four", 3.0)],
+            ..Default::default()
+        };
+
+        // 0-10s and 8-20s overlap; the boundary is at 9s
+        let joined = stitch(vec![(0..160_000, first), (128_000..320_000, second)]);
+        assert_eq!(joined.text, "one two three four");
+        let words: Vec<_> = joined
+            .words
+            .iter()
+            .map(|w| (w.word.as_str(), w.start))
+            .collect();
+        assert_eq!(
+            words,
+            vec![("one", 0.5), ("two", 8.5), ("three", 9.5), ("four", 11.0)]
+        );
+    }
+}
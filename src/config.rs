@@ -1,16 +1,266 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Deserialize)]
+use crate::encode::Codec;
+
+#[derive(Deserialize, Default)]
 struct ConfigFile {
     api_key: Option<String>,
     model: Option<String>,
+    backend: Option<String>,
+    base_url: Option<String>,
+    headers: Option<BTreeMap<String, String>>,
+    model_path: Option<PathBuf>,
+    threads: Option<usize>,
+    language: Option<String>,
+    prompt: Option<String>,
+    temperature: Option<f32>,
+    response_format: Option<String>,
+    timestamp_granularities: Option<Vec<String>>,
+    incremental: Option<bool>,
+    upload_format: Option<String>,
+    stream: Option<bool>,
+    azure_endpoint: Option<String>,
+    azure_deployment: Option<String>,
+    azure_api_version: Option<String>,
+    connect_timeout: Option<f32>,
+    read_timeout: Option<f32>,
+    proxy: Option<String>,
+    no_proxy: Option<String>,
+    ca_certs: Option<Vec<PathBuf>>,
+    prices: Option<BTreeMap<String, f64>>,
+    monthly_budget: Option<f64>,
+    over_budget: Option<String>,
+    input_device: Option<String>,
+    auto_stop: Option<f32>,
+    max_duration: Option<f32>,
+    trim_silence: Option<bool>,
+    trim_padding: Option<f32>,
+}
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+/// Azure OpenAI API version with audio transcription support
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-06-01";
+const DEFAULT_CONNECT_TIMEOUT_SECS: f32 = 10.0;
+/// Long uploads are transcribed before the response starts, so this is generous
+const DEFAULT_READ_TIMEOUT_SECS: f32 = 120.0;
+/// Hands-free recordings end here even if the room never goes quiet
+const DEFAULT_MAX_DURATION_SECS: f32 = 120.0;
+/// Silence left around speech when trimming, so words aren't clipped
+const DEFAULT_TRIM_PADDING_SECS: f32 = 0.3;
+
+/// Which transcription provider `transcribe::backend_from_config` builds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    OpenAi,
+    /// OpenAI realtime transcription, streamed while recording
+    Realtime,
+    /// Azure OpenAI deployment
+    Azure,
+    /// Offline whisper.cpp, needs the `local` cargo feature
+    Local,
+}
+
+impl BackendKind {
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "openai" => Some(Self::OpenAi),
+            "realtime" => Some(Self::Realtime),
+            "azure" => Some(Self::Azure),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+}
+
+/// Where an Azure OpenAI Whisper deployment lives
+#[derive(Debug, Clone)]
+pub struct AzureConfig {
+    /// Resource endpoint, e.g. `https://NAME.openai.azure.com`
+    pub endpoint: String,
+    pub deployment: String,
+    pub api_version: String,
+}
+
+/// What happens once the month's estimated spend reaches `monthly`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    /// USD per calendar month
+    pub monthly: f64,
+    pub over: OverBudget,
+}
+
+/// Hands-free mode: recording stops by itself once speech is over
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoStop {
+    /// Silence after speech that ends the recording
+    pub silence: Duration,
+    /// Hard limit on the whole recording
+    pub max_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// Don't transcribe remotely until next month
+    Refuse,
+    /// Use the configured local backend instead
+    Local,
 }
 
 pub struct Config {
-    pub api_key: String,
+    pub backend: BackendKind,
+    /// `None` for self-hosted endpoints that need no auth
+    pub api_key: Option<String>,
     pub model: String,
+    /// OpenAI-compatible API root, including the `/v1` suffix
+    pub base_url: String,
+    /// Extra headers sent with every transcription request
+    pub headers: BTreeMap<String, String>,
+    /// Set when `backend = "azure"`
+    pub azure: Option<AzureConfig>,
+    /// GGML model file for the local backend
+    #[cfg_attr(not(feature = "local"), allow(dead_code))]
+    pub model_path: Option<PathBuf>,
+    /// CPU threads for local inference
+    #[cfg_attr(not(feature = "local"), allow(dead_code))]
+    pub threads: usize,
+    /// ISO-639-1 spoken language; `None` means auto-detect
+    pub language: Option<String>,
+    /// Text to bias vocabulary and spelling
+    pub prompt: Option<String>,
+    /// Sampling temperature, 0.0 to 1.0
+    pub temperature: Option<f32>,
+    /// Request `verbose_json` for segments, confidence and language
+    pub verbose: bool,
+    /// `segment` and/or `word` timestamps (implies `verbose`)
+    pub timestamp_granularities: Vec<String>,
+    /// Transcribe long dictations in segments cut at pauses while recording
+    pub incremental: bool,
+    /// Microphone by name or part of one; `None` means the system default
+    pub input_device: Option<String>,
+    pub auto_stop: Option<AutoStop>,
+    /// Seconds of silence kept before and after speech, which is all that
+    /// gets uploaded; `None` sends recordings untrimmed
+    pub trim_padding: Option<f32>,
+    /// Audio encoding for uploads to remote backends
+    pub upload_format: Codec,
+    /// Stream partial text while transcribing (gpt-4o transcription models)
+    pub stream: bool,
+    /// Limit for DNS, TCP and TLS setup
+    pub connect_timeout: Duration,
+    /// Limit for waiting on the response, and between its chunks
+    pub read_timeout: Duration,
+    /// `http://`, `https://` or `socks5://` proxy for every request. Unset
+    /// means the usual `HTTPS_PROXY`/`ALL_PROXY`/`HTTP_PROXY` variables.
+    pub proxy: Option<String>,
+    /// Hosts that bypass `proxy`, comma-separated; defaults to `NO_PROXY`
+    pub no_proxy: Option<String>,
+    /// PEM bundles trusted in addition to the built-in roots
+    pub ca_certs: Vec<PathBuf>,
+    /// USD per minute of audio, by model (Azure: by deployment)
+    pub prices: BTreeMap<String, f64>,
+    pub budget: Option<Budget>,
+    /// Tried in order when this backend fails with a retryable error. Each
+    /// `[[fallback]]` table inherits the top-level settings it doesn't set,
+    /// except for `SERVER_KEYS` when it talks to a different server.
+    pub fallbacks: Vec<Config>,
+}
+
+/// Per-invocation request parameters from the command line; they win over
+/// the config file
+#[derive(Debug, Default, PartialEq)]
+pub struct Overrides {
+    pub language: Option<String>,
+    pub prompt: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+impl Overrides {
+    /// Remove `--language`, `--prompt` and `--temperature` (each followed by
+    /// a value) from `args`, leaving the rest for command dispatch
+    pub fn take_from_args(args: &mut Vec<String>) -> Result<Self, String> {
+        let mut overrides = Self::default();
+        let mut i = 1;
+        while i < args.len() {
+            let flag = args[i].as_str();
+            if !matches!(flag, "--language" | "--prompt" | "--temperature") {
+                i += 1;
+                continue;
+            }
+            let value = args
+                .get(i + 1)
+                .cloned()
+                .ok_or_else(|| format!("{flag} needs a value"))?;
+            match flag {
+                "--language" => overrides.language = Some(value),
+                "--prompt" => overrides.prompt = Some(value),
+                _ => {
+                    let t = value
+                        .parse()
+                        .map_err(|_| format!("--temperature: \"{value}\" is not a number"))?;
+                    overrides.temperature = Some(t);
+                }
+            }
+            args.drain(i..i + 2);
+        }
+        Ok(overrides)
+    }
+}
+
+/// ISO-639-1 codes accepted by Whisper
+const WHISPER_LANGUAGES: &[&str] = &[
+    "af", "am", "ar", "as", "az", "ba", "be", "bg", "bn", "bo", "br", "bs", "ca", "cs", "cy", "da",
+    "de", "el", "en", "es", "et", "eu", "fa", "fi", "fo", "fr", "gl", "gu", "ha", "he", "hi", "hr",
+    "ht", "hu", "hy", "id", "is", "it", "ja", "jw", "ka", "kk", "km", "kn", "ko", "la", "lb", "ln",
+    "lo", "lt", "lv", "mg", "mi", "mk", "ml", "mn", "mr", "ms", "mt", "my", "ne", "nl", "nn", "no",
+    "oc", "pa", "pl", "ps", "pt", "ro", "ru", "sa", "sd", "si", "sk", "sl", "sn", "so", "sq", "sr",
+    "su", "sv", "sw", "ta", "te", "tg", "th", "tk", "tl", "tr", "tt", "uk", "ur", "uz", "vi", "yi",
+    "yo", "zh",
+];
+
+/// Normalize a language setting. `"auto"` (or empty) means detect.
+pub fn parse_language(value: &str) -> Result<Option<String>, String> {
+    let code = value.trim().to_ascii_lowercase();
+    if code.is_empty() || code == "auto" {
+        return Ok(None);
+    }
+    if WHISPER_LANGUAGES.contains(&code.as_str()) {
+        Ok(Some(code))
+    } else {
+        Err(format!(
+            "\"{value}\" is not a supported ISO-639-1 language code (e.g. \"en\", \"de\", or \"auto\")"
+        ))
+    }
+}
+
+/// Timeouts are given in seconds and must be positive
+pub fn parse_timeout(name: &str, secs: f32) -> Result<Duration, String> {
+    match Duration::try_from_secs_f32(secs) {
+        Ok(d) if !d.is_zero() => Ok(d),
+        _ => Err(format!(
+            "{name} must be a positive number of seconds, got {secs}"
+        )),
+    }
+}
+
+/// The environment variable that overrides `api_key`. An OpenAI key in the
+/// environment must not shadow the Azure key in the config file.
+fn api_key_var(backend: BackendKind) -> &'static str {
+    match backend {
+        BackendKind::Azure => "AZURE_OPENAI_API_KEY",
+        _ => "OPENAI_API_KEY",
+    }
+}
+
+pub fn check_temperature(t: f32) -> Result<f32, String> {
+    if (0.0..=1.0).contains(&t) {
+        Ok(t)
+    } else {
+        Err(format!("temperature must be between 0.0 and 1.0, got {t}"))
+    }
 }
 
 pub fn config_path() -> PathBuf {
@@ -20,26 +270,86 @@ pub fn config_path() -> PathBuf {
         .join("config.toml")
 }
 
+/// Where history and the offline queue live (separate from the config file)
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("whisper-paste")
+}
+
 pub fn save_api_key(key: &str) -> Result<(), String> {
+    save_settings(&[("api_key", key)])
+}
+
+/// Set top-level string keys in the config file, keeping everything else
+fn save_settings(settings: &[(&str, &str)]) -> Result<(), String> {
     let path = config_path();
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).map_err(|e| format!("failed to create config dir: {e}"))?;
     }
 
-    // If config exists, update the key in place; otherwise create new
-    let content = if path.exists() {
-        let existing = std::fs::read_to_string(&path).unwrap_or_default();
-        let mut cfg: toml::Table = toml::from_str(&existing).unwrap_or_default();
-        cfg.insert("api_key".into(), toml::Value::String(key.to_string()));
-        toml::to_string_pretty(&cfg).map_err(|e| e.to_string())?
-    } else {
-        format!("api_key = \"{key}\"\n")
-    };
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+    let mut cfg: toml::Table = toml::from_str(&existing).unwrap_or_default();
+    for (key, value) in settings {
+        cfg.insert(key.to_string(), toml::Value::String(value.to_string()));
+    }
+    let content = toml::to_string_pretty(&cfg).map_err(|e| e.to_string())?;
 
     std::fs::write(&path, content).map_err(|e| format!("failed to write config: {e}"))?;
     Ok(())
 }
 
+fn prompt_line(label: &str) -> String {
+    print!("{label}");
+    std::io::stdout().flush().ok();
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).ok();
+    line.trim().to_string()
+}
+
+fn existing_backend() -> Option<String> {
+    let existing = std::fs::read_to_string(config_path()).ok()?;
+    toml::from_str::<ConfigFile>(&existing).ok()?.backend
+}
+
+fn setup_azure() {
+    let path = config_path();
+    println!();
+    let endpoint = prompt_line("Resource endpoint (https://NAME.openai.azure.com): ");
+    let deployment = prompt_line("Whisper deployment name: ");
+    let api_version = prompt_line(&format!("API version [{DEFAULT_AZURE_API_VERSION}]: "));
+    let api_version = if api_version.is_empty() {
+        DEFAULT_AZURE_API_VERSION.to_string()
+    } else {
+        api_version
+    };
+    let key = prompt_line("Azure OpenAI API key: ");
+
+    if endpoint.is_empty() || deployment.is_empty() || key.is_empty() {
+        eprintln!("Endpoint, deployment and key are all required. Aborting.");
+        std::process::exit(1);
+    }
+
+    let settings = [
+        ("backend", "azure"),
+        ("azure_endpoint", endpoint.as_str()),
+        ("azure_deployment", deployment.as_str()),
+        ("azure_api_version", api_version.as_str()),
+        ("api_key", key.as_str()),
+    ];
+    match save_settings(&settings) {
+        Ok(()) => {
+            println!("Azure settings saved to {}", path.display());
+            println!();
+            println!("You're all set! Run `whisper-paste` to start.");
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
 pub fn setup_interactive() {
     let path = config_path();
     println!("whisper-paste setup");
@@ -47,10 +357,20 @@ pub fn setup_interactive() {
     println!("Config location: {}", path.display());
     println!();
 
-    // Check if key already exists
+    println!("Transcription provider:");
+    println!("  1) OpenAI");
+    println!("  2) Azure OpenAI");
+    if prompt_line("Choose [1]: ") == "2" {
+        setup_azure();
+        return;
+    }
+    println!();
+
+    // Check if key already exists (an Azure key is no use for OpenAI)
     if let Ok(existing) = std::fs::read_to_string(&path) {
         if let Ok(cfg) = toml::from_str::<ConfigFile>(&existing) {
-            if let Some(ref key) = cfg.api_key {
+            let azure = cfg.backend.as_deref() == Some("azure");
+            if let Some(key) = cfg.api_key.as_ref().filter(|_| !azure) {
                 if key != "sk-your-key-here" {
                     let masked = format!("{}...{}", &key[..7.min(key.len())], &key[key.len().saturating_sub(4)..]);
                     println!("Existing API key found: {masked}");
@@ -78,7 +398,13 @@ pub fn setup_interactive() {
         std::process::exit(1);
     }
 
-    match save_api_key(key) {
+    // Picking OpenAI here switches away from a previously configured Azure
+    let saved = if existing_backend().as_deref() == Some("azure") {
+        save_settings(&[("api_key", key), ("backend", "openai")])
+    } else {
+        save_api_key(key)
+    };
+    match saved {
         Ok(()) => {
             println!("API key saved to {}", path.display());
             println!();
@@ -91,44 +417,303 @@ pub fn setup_interactive() {
     }
 }
 
-pub fn load_config() -> Config {
+pub fn load_config(overrides: &Overrides) -> Config {
     let path = config_path();
 
-    let file_cfg: ConfigFile = if path.exists() {
+    let mut table: toml::Table = if path.exists() {
         let content = std::fs::read_to_string(&path).unwrap_or_default();
-        toml::from_str(&content).unwrap_or(ConfigFile {
-            api_key: None,
-            model: None,
-        })
+        toml::from_str(&content).unwrap_or_default()
     } else {
-        ConfigFile {
-            api_key: None,
-            model: None,
+        toml::Table::new()
+    };
+    let fallback_tables = split_fallbacks(&mut table);
+
+    let mut cfg = build_config(parse_config_file(table.clone()), overrides);
+    for (i, fallback) in fallback_tables.into_iter().enumerate() {
+        let Some(merged) = merge_fallback(&table, fallback) else {
+            eprintln!(
+                "[[fallback]] number {} in {} needs a backend",
+                i + 1,
+                path.display()
+            );
+            std::process::exit(1);
+        };
+        let fallback = build_config(parse_config_file(merged), overrides);
+        cfg.fallbacks.push(fallback);
+    }
+    cfg
+}
+
+/// Settings that belong to one server: its address, credentials, extra
+/// headers and model names
+const SERVER_KEYS: [&str; 4] = ["base_url", "api_key", "headers", "model"];
+
+/// A `[[fallback]]` table with the top-level settings it doesn't set.
+/// `SERVER_KEYS` only carry over to the same kind of backend at the same
+/// address, so a self-hosted server's headers never go to api.openai.com.
+/// `None` if the fallback doesn't name its backend.
+fn merge_fallback(top: &toml::Table, fallback: toml::Table) -> Option<toml::Table> {
+    let kind = |table: &toml::Table| {
+        table
+            .get("backend")
+            .and_then(toml::Value::as_str)
+            .map(str::to_ascii_lowercase)
+    };
+    let fallback_kind = kind(&fallback)?;
+    let same_server = fallback_kind == kind(top).unwrap_or_else(|| "openai".to_string())
+        && !fallback.contains_key("base_url");
+
+    let mut merged = top.clone();
+    if !same_server {
+        for key in SERVER_KEYS {
+            merged.remove(key);
         }
+    }
+    merged.extend(fallback);
+    Some(merged)
+}
+
+/// Take the `[[fallback]]` tables out of the top-level config
+fn split_fallbacks(table: &mut toml::Table) -> Vec<toml::Table> {
+    match table.remove("fallback") {
+        Some(toml::Value::Array(entries)) => entries
+            .into_iter()
+            .filter_map(|entry| match entry {
+                toml::Value::Table(t) => Some(t),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn parse_config_file(table: toml::Table) -> ConfigFile {
+    toml::Value::Table(table).try_into().unwrap_or_default()
+}
+
+/// Validate one backend's settings, exiting with a message on anything wrong
+fn build_config(file_cfg: ConfigFile, overrides: &Overrides) -> Config {
+    let path = config_path();
+
+    let backend = match file_cfg.backend.as_deref() {
+        None => BackendKind::OpenAi,
+        Some(name) => BackendKind::parse(name).unwrap_or_else(|| {
+            eprintln!("Unknown backend \"{name}\" in {}", path.display());
+            eprintln!("Supported backends: openai, realtime, azure, local");
+            std::process::exit(1);
+        }),
     };
 
-    let api_key = std::env::var("OPENAI_API_KEY")
+    let base_url = file_cfg
+        .base_url
+        .map(|u| u.trim_end_matches('/').to_string())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+
+    let api_key = std::env::var(api_key_var(backend))
         .ok()
         .or(file_cfg.api_key)
-        .unwrap_or_else(|| {
-            eprintln!("No API key found.");
-            eprintln!();
-            eprintln!("Run:  whisper-paste --setup");
-            eprintln!("  or: whisper-paste --api-key sk-your-key");
-            eprintln!("  or: export OPENAI_API_KEY=\"sk-your-key\"");
-            std::process::exit(1);
-        });
+        .filter(|k| !k.is_empty());
 
-    if api_key == "sk-your-key-here" {
+    if api_key.as_deref() == Some("sk-your-key-here") {
         eprintln!("API key is still the placeholder. Run: whisper-paste --setup");
         std::process::exit(1);
     }
 
+    // Self-hosted servers often run without auth; only the hosted API insists
+    if api_key.is_none()
+        && matches!(backend, BackendKind::OpenAi | BackendKind::Realtime)
+        && base_url == DEFAULT_BASE_URL
+    {
+        eprintln!("No API key found.");
+        eprintln!();
+        eprintln!("Run:  whisper-paste --setup");
+        eprintln!("  or: whisper-paste --api-key sk-your-key");
+        eprintln!("  or: export OPENAI_API_KEY=\"sk-your-key\"");
+        std::process::exit(1);
+    }
+
+    if api_key.is_none() && backend == BackendKind::Azure {
+        eprintln!("No Azure OpenAI API key found.");
+        eprintln!();
+        eprintln!("Run:  whisper-paste --setup");
+        eprintln!("  or: export AZURE_OPENAI_API_KEY=\"your-key\"");
+        std::process::exit(1);
+    }
+
+    let azure = match (file_cfg.azure_endpoint, file_cfg.azure_deployment) {
+        (Some(endpoint), Some(deployment)) => Some(AzureConfig {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            deployment,
+            api_version: file_cfg
+                .azure_api_version
+                .unwrap_or_else(|| DEFAULT_AZURE_API_VERSION.to_string()),
+        }),
+        _ if backend == BackendKind::Azure => {
+            eprintln!("backend = \"azure\" needs azure_endpoint and azure_deployment, e.g.");
+            eprintln!("  azure_endpoint = \"https://NAME.openai.azure.com\"");
+            eprintln!("  azure_deployment = \"whisper\"");
+            eprintln!("or run: whisper-paste --setup");
+            std::process::exit(1);
+        }
+        _ => None,
+    };
+
     let model = file_cfg
         .model
         .unwrap_or_else(|| "whisper-1".to_string());
+    // whisper-1 rejects `stream`; the gpt-4o transcription models accept it
+    let stream = file_cfg
+        .stream
+        .unwrap_or_else(|| model.starts_with("gpt-4o"));
+
+    if backend == BackendKind::Local && file_cfg.model_path.is_none() {
+        eprintln!("backend = \"local\" needs a model_path, e.g.");
+        eprintln!("  model_path = \"/path/to/ggml-base.en.bin\"");
+        std::process::exit(1);
+    }
+
+    let threads = file_cfg.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get().min(8))
+            .unwrap_or(4)
+    });
 
-    Config { api_key, model }
+    let invalid = |e: String| -> ! {
+        eprintln!("Invalid setting: {e}");
+        std::process::exit(1);
+    };
+
+    let language = match overrides.language.as_ref().or(file_cfg.language.as_ref()) {
+        Some(value) => parse_language(value).unwrap_or_else(|e| invalid(e)),
+        None => None,
+    };
+
+    let prompt = overrides
+        .prompt
+        .clone()
+        .or(file_cfg.prompt)
+        .filter(|p| !p.trim().is_empty());
+
+    let temperature = overrides
+        .temperature
+        .or(file_cfg.temperature)
+        .map(|t| check_temperature(t).unwrap_or_else(|e| invalid(e)));
+
+    let timestamp_granularities = file_cfg.timestamp_granularities.unwrap_or_default();
+    for granularity in &timestamp_granularities {
+        if granularity != "segment" && granularity != "word" {
+            invalid(format!(
+                "timestamp_granularities: \"{granularity}\" is not \"segment\" or \"word\""
+            ));
+        }
+    }
+
+    let verbose = match file_cfg.response_format.as_deref() {
+        None | Some("json") => !timestamp_granularities.is_empty(),
+        Some("verbose_json") => true,
+        Some(other) => invalid(format!(
+            "response_format: \"{other}\" is not \"json\" or \"verbose_json\""
+        )),
+    };
+
+    let upload_format = match file_cfg.upload_format.as_deref() {
+        None => Codec::Wav,
+        Some(value) => match Codec::parse(value) {
+            Some(Codec::Opus) if !cfg!(feature = "opus") => {
+                eprintln!(
+                    "upload_format = \"opus\" needs a build with `--features opus`; using WAV"
+                );
+                Codec::Wav
+            }
+            Some(codec) => codec,
+            None => invalid(format!(
+                "upload_format: \"{value}\" is not \"wav\", \"flac\" or \"opus\""
+            )),
+        },
+    };
+
+    let connect_timeout = parse_timeout(
+        "connect_timeout",
+        file_cfg
+            .connect_timeout
+            .unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+    )
+    .unwrap_or_else(|e| invalid(e));
+    let read_timeout = parse_timeout(
+        "read_timeout",
+        file_cfg.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT_SECS),
+    )
+    .unwrap_or_else(|e| invalid(e));
+
+    let auto_stop = file_cfg.auto_stop.map(|silence| AutoStop {
+        silence: parse_timeout("auto_stop", silence).unwrap_or_else(|e| invalid(e)),
+        max_duration: parse_timeout(
+            "max_duration",
+            file_cfg.max_duration.unwrap_or(DEFAULT_MAX_DURATION_SECS),
+        )
+        .unwrap_or_else(|e| invalid(e)),
+    });
+
+    let trim_padding = file_cfg.trim_padding.unwrap_or(DEFAULT_TRIM_PADDING_SECS);
+    if !(trim_padding.is_finite() && trim_padding >= 0.0) {
+        invalid(format!(
+            "trim_padding must be 0 or more seconds, got {trim_padding}"
+        ));
+    }
+    let trim_padding = file_cfg
+        .trim_silence
+        .unwrap_or(true)
+        .then_some(trim_padding);
+
+    let mut prices: BTreeMap<String, f64> = crate::usage::DEFAULT_PRICES
+        .iter()
+        .map(|&(model, price)| (model.to_string(), price))
+        .collect();
+    prices.extend(file_cfg.prices.unwrap_or_default());
+
+    let over = match file_cfg.over_budget.as_deref() {
+        None | Some("refuse") => OverBudget::Refuse,
+        Some("local") => OverBudget::Local,
+        Some(other) => invalid(format!(
+            "over_budget: \"{other}\" is not \"refuse\" or \"local\""
+        )),
+    };
+    let budget = file_cfg.monthly_budget.map(|monthly| {
+        if monthly.is_nan() || monthly < 0.0 {
+            invalid(format!("monthly_budget must be 0 or more, got {monthly}"));
+        }
+        Budget { monthly, over }
+    });
+
+    Config {
+        backend,
+        api_key,
+        model,
+        base_url,
+        headers: file_cfg.headers.unwrap_or_default(),
+        azure,
+        model_path: file_cfg.model_path,
+        threads,
+        language,
+        prompt,
+        temperature,
+        verbose,
+        timestamp_granularities,
+        incremental: file_cfg.incremental.unwrap_or(true),
+        input_device: file_cfg.input_device.filter(|d| !d.trim().is_empty()),
+        auto_stop,
+        trim_padding,
+        upload_format,
+        stream,
+        connect_timeout,
+        read_timeout,
+        proxy: file_cfg.proxy.filter(|p| !p.trim().is_empty()),
+        no_proxy: file_cfg.no_proxy,
+        ca_certs: file_cfg.ca_certs.unwrap_or_default(),
+        prices,
+        budget,
+        fallbacks: Vec::new(),
+    }
 }
 
 #[cfg(test)]
@@ -173,5 +758,224 @@ mod tests {
         let cfg: ConfigFile = toml::from_str("").unwrap();
         assert!(cfg.api_key.is_none());
         assert!(cfg.model.is_none());
+        assert!(cfg.backend.is_none());
+    }
+
+    #[test]
+    fn config_file_with_base_url_and_headers() {
+        let toml_str = r#"
+base_url = "http://localhost:8000/v1"
+
+[headers]
+X-Team = "voice"
+"#;
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert!(cfg.api_key.is_none());
+        assert_eq!(cfg.base_url.unwrap(), "http://localhost:8000/v1");
+        assert_eq!(cfg.headers.unwrap()["X-Team"], "voice");
+    }
+
+    #[test]
+    fn fallbacks_inherit_top_level_settings() {
+        let toml_str = r#"
+base_url = "http://localhost:8000/v1"
+model = "large-v3"
+language = "de"
+
+[headers]
+Authorization = "Bearer gpu-box-token"
+
+[[fallback]]
+backend = "openai"
+base_url = "https://api.openai.com/v1"
+
+[[fallback]]
+backend = "local"
+model_path = "/models/ggml-base.bin"
+
+[[fallback]]
+backend = "openai"
+model = "whisper-1"
+"#;
+        let mut table: toml::Table = toml::from_str(toml_str).unwrap();
+        let fallbacks = split_fallbacks(&mut table);
+        assert_eq!(fallbacks.len(), 3);
+        assert!(!table.contains_key("fallback"));
+
+        // Another server: only the general settings carry over
+        let cfg = parse_config_file(merge_fallback(&table, fallbacks[0].clone()).unwrap());
+        assert_eq!(cfg.base_url.unwrap(), "https://api.openai.com/v1");
+        assert_eq!(cfg.language.unwrap(), "de");
+        assert!(cfg.headers.is_none());
+        assert!(cfg.model.is_none());
+
+        let cfg = parse_config_file(merge_fallback(&table, fallbacks[1].clone()).unwrap());
+        assert_eq!(cfg.backend.unwrap(), "local");
+        assert!(cfg.base_url.is_none());
+
+        // The same server with another model keeps its address and headers
+        let cfg = parse_config_file(merge_fallback(&table, fallbacks[2].clone()).unwrap());
+        assert_eq!(cfg.base_url.unwrap(), "http://localhost:8000/v1");
+        assert_eq!(cfg.model.unwrap(), "whisper-1");
+        assert!(cfg.headers.unwrap().contains_key("Authorization"));
+    }
+
+    #[test]
+    fn fallback_needs_its_own_backend() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+backend = "realtime"
+
+[[fallback]]
+model = "whisper-1"
+"#,
+        )
+        .unwrap();
+        let fallbacks = split_fallbacks(&mut table);
+        assert!(merge_fallback(&table, fallbacks[0].clone()).is_none());
+    }
+
+    #[test]
+    fn config_file_prices_and_budget() {
+        let toml_str = r#"
+monthly_budget = 20.0
+over_budget = "local"
+
+[prices]
+my-deployment = 0.006
+"#;
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert_eq!(cfg.monthly_budget, Some(20.0));
+        assert_eq!(cfg.over_budget.as_deref(), Some("local"));
+        assert_eq!(cfg.prices.unwrap()["my-deployment"], 0.006);
+    }
+
+    #[test]
+    fn config_file_proxy_and_ca_certs() {
+        let toml_str = r#"
+proxy = "http://proxy.corp:3128"
+no_proxy = "localhost,.corp"
+ca_certs = ["/etc/ssl/corp-root.pem"]
+"#;
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert_eq!(cfg.proxy.unwrap(), "http://proxy.corp:3128");
+        assert_eq!(cfg.no_proxy.unwrap(), "localhost,.corp");
+        assert_eq!(
+            cfg.ca_certs.unwrap(),
+            vec![PathBuf::from("/etc/ssl/corp-root.pem")]
+        );
+    }
+
+    #[test]
+    fn azure_ignores_the_openai_key_variable() {
+        assert_eq!(api_key_var(BackendKind::Azure), "AZURE_OPENAI_API_KEY");
+        assert_eq!(api_key_var(BackendKind::OpenAi), "OPENAI_API_KEY");
+        assert_eq!(api_key_var(BackendKind::Realtime), "OPENAI_API_KEY");
+    }
+
+    #[test]
+    fn config_file_azure_backend() {
+        let toml_str = "backend = \"azure\"\nazure_endpoint = \"https://contoso.openai.azure.com\"\nazure_deployment = \"whisper\"\n";
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            BackendKind::parse(&cfg.backend.unwrap()),
+            Some(BackendKind::Azure)
+        );
+        assert_eq!(
+            cfg.azure_endpoint.unwrap(),
+            "https://contoso.openai.azure.com"
+        );
+        assert_eq!(cfg.azure_deployment.unwrap(), "whisper");
+        assert!(cfg.azure_api_version.is_none());
+    }
+
+    #[test]
+    fn config_file_local_backend() {
+        let toml_str = "backend = \"local\"\nmodel_path = \"/models/ggml-base.bin\"\nthreads = 4\nlanguage = \"de\"\n";
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert_eq!(cfg.backend.unwrap(), "local");
+        assert_eq!(
+            cfg.model_path.unwrap(),
+            PathBuf::from("/models/ggml-base.bin")
+        );
+        assert_eq!(cfg.threads, Some(4));
+        assert_eq!(cfg.language.unwrap(), "de");
+    }
+
+    #[test]
+    fn config_file_verbose_response() {
+        let toml_str = "response_format = \"verbose_json\"\ntimestamp_granularities = [\"segment\", \"word\"]\n";
+        let cfg: ConfigFile = toml::from_str(toml_str).unwrap();
+        assert_eq!(cfg.response_format.unwrap(), "verbose_json");
+        assert_eq!(
+            cfg.timestamp_granularities.unwrap(),
+            vec!["segment", "word"]
+        );
+    }
+
+    #[test]
+    fn config_file_upload_format() {
+        let cfg: ConfigFile = toml::from_str("upload_format = \"flac\"\n").unwrap();
+        assert_eq!(Codec::parse(&cfg.upload_format.unwrap()), Some(Codec::Flac));
+    }
+
+    #[test]
+    fn language_codes_are_validated() {
+        assert_eq!(parse_language("de").unwrap(), Some("de".to_string()));
+        assert_eq!(parse_language(" EN ").unwrap(), Some("en".to_string()));
+        assert_eq!(parse_language("auto").unwrap(), None);
+        assert!(parse_language("german").is_err());
+        assert!(parse_language("xx").is_err());
+    }
+
+    #[test]
+    fn temperature_range() {
+        assert!(check_temperature(0.0).is_ok());
+        assert!(check_temperature(1.0).is_ok());
+        assert!(check_temperature(1.5).is_err());
+        assert!(check_temperature(-0.1).is_err());
+    }
+
+    #[test]
+    fn timeouts_must_be_positive() {
+        let cfg: ConfigFile = toml::from_str("connect_timeout = 2.5\n").unwrap();
+        assert_eq!(
+            parse_timeout("connect_timeout", cfg.connect_timeout.unwrap()),
+            Ok(Duration::from_millis(2500))
+        );
+        assert!(parse_timeout("read_timeout", 0.0).is_err());
+        assert!(parse_timeout("read_timeout", -1.0).is_err());
+        assert!(parse_timeout("read_timeout", f32::NAN).is_err());
+    }
+
+    #[test]
+    fn overrides_are_taken_from_args() {
+        let mut args: Vec<String> = [
+            "whisper-paste",
+            "--language",
+            "fr",
+            "--no-ui",
+            "--temperature",
+            "0.2",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let overrides = Overrides::take_from_args(&mut args).unwrap();
+        assert_eq!(overrides.language.as_deref(), Some("fr"));
+        assert_eq!(overrides.temperature, Some(0.2));
+        assert!(overrides.prompt.is_none());
+        assert_eq!(args, vec!["whisper-paste", "--no-ui"]);
+
+        let mut missing = vec!["whisper-paste".to_string(), "--prompt".to_string()];
+        assert!(Overrides::take_from_args(&mut missing).is_err());
+    }
+
+    #[test]
+    fn backend_kind_parses_case_insensitively() {
+        assert_eq!(BackendKind::parse("openai"), Some(BackendKind::OpenAi));
+        assert_eq!(BackendKind::parse("OpenAI"), Some(BackendKind::OpenAi));
+        assert_eq!(BackendKind::parse("local"), Some(BackendKind::Local));
+        assert_eq!(BackendKind::parse("nope"), None);
     }
 }
@@ -0,0 +1,605 @@
+//! Upload encodings for 16kHz mono recordings. WAV is the baseline; FLAC is
+//! lossless at roughly half the size; Opus (behind the `opus` feature) is
+//! lossy and about a tenth of that again.
+
+use crate::audio;
+
+const SAMPLE_RATE: u32 = 16000;
+
+/// Upload codec, chosen by `upload_format` in the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Wav,
+    Flac,
+    Opus,
+}
+
+impl Codec {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            "opus" | "ogg" => Some(Self::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Wav => "audio.wav",
+            Self::Flac => "audio.flac",
+            Self::Opus => "audio.ogg",
+        }
+    }
+
+    /// Longest recording that stays under `max_bytes` once encoded
+    pub fn max_samples(self, max_bytes: usize) -> usize {
+        // Worst case per second: FLAC falls back to verbatim frames, just
+        // above WAV; Opus is capped by its bitrate plus Ogg framing
+        let bytes_per_sec = match self {
+            Self::Wav => 32_000,
+            Self::Flac => 32_100,
+            Self::Opus => 4_000,
+        };
+        max_bytes.saturating_sub(1024) / bytes_per_sec * SAMPLE_RATE as usize
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Wav => "audio/wav",
+            Self::Flac => "audio/flac",
+            Self::Opus => "audio/ogg",
+        }
+    }
+}
+
+/// Encode with `codec`. There's no falling back to WAV when Opus fails:
+/// chunks were sized for Opus, and as WAV they'd be over the upload limit.
+pub fn encode(samples: &[f32], codec: Codec) -> Result<Vec<u8>, String> {
+    match codec {
+        Codec::Wav => Ok(audio::samples_to_wav(samples)),
+        Codec::Flac => Ok(samples_to_flac(samples)),
+        Codec::Opus => samples_to_opus(samples).map_err(|e| format!("Opus encoding failed: {e}")),
+    }
+}
+
+fn to_i16(samples: &[f32]) -> Vec<i32> {
+    samples
+        .iter()
+        .map(|&s| (s * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i32)
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// FLAC: fixed-blocksize frames, fixed linear predictors (order 0-4) and
+// partitioned Rice residuals. No LPC, so a little larger than `flac`'s
+// output, but lossless and cheap enough to run on every upload.
+// ---------------------------------------------------------------------------
+
+const FLAC_BLOCK_SIZE: usize = 4096;
+const MAX_PARTITION_ORDER: u32 = 6;
+/// 4-bit Rice parameters; 15 is the escape code
+const MAX_RICE_PARAM: u32 = 14;
+
+pub fn samples_to_flac(samples: &[f32]) -> Vec<u8> {
+    let pcm = to_i16(samples);
+    let mut w = BitWriter::default();
+
+    w.bytes.extend_from_slice(b"fLaC");
+
+    // STREAMINFO, the only (and so last) metadata block
+    w.write(1, 1);
+    w.write(0, 7);
+    w.write(34, 24);
+    w.write(FLAC_BLOCK_SIZE as u64, 16); // min block size
+    w.write(FLAC_BLOCK_SIZE as u64, 16); // max block size
+    w.write(0, 24); // min frame size: unknown
+    w.write(0, 24); // max frame size: unknown
+    w.write(SAMPLE_RATE as u64, 20);
+    w.write(0, 3); // channels - 1
+    w.write(15, 5); // bits per sample - 1
+    w.write(pcm.len() as u64, 36);
+    w.bytes.extend_from_slice(&[0; 16]); // MD5 not computed
+
+    for (frame_number, block) in pcm.chunks(FLAC_BLOCK_SIZE).enumerate() {
+        write_frame(&mut w, frame_number as u64, block);
+    }
+
+    w.bytes
+}
+
+fn write_frame(w: &mut BitWriter, frame_number: u64, block: &[i32]) {
+    let frame_start = w.bytes.len();
+
+    w.write(0b11_1111_1111_1110, 14); // sync
+    w.write(0, 1); // reserved
+    w.write(0, 1); // fixed block size
+    if block.len() == FLAC_BLOCK_SIZE {
+        w.write(0b1100, 4); // 4096
+    } else {
+        w.write(0b0111, 4); // 16-bit size follows the header
+    }
+    w.write(0b0101, 4); // 16kHz
+    w.write(0b0000, 4); // mono
+    w.write(0b100, 3); // 16 bits per sample
+    w.write(0, 1); // reserved
+    write_utf8_number(w, frame_number);
+    if block.len() != FLAC_BLOCK_SIZE {
+        w.write(block.len() as u64 - 1, 16);
+    }
+    let crc = crc8(&w.bytes[frame_start..]);
+    w.write(crc as u64, 8);
+
+    write_subframe(w, block);
+
+    w.align();
+    let crc = crc16(&w.bytes[frame_start..]);
+    w.write(crc as u64, 16);
+}
+
+fn write_subframe(w: &mut BitWriter, block: &[i32]) {
+    if block.iter().all(|&s| s == block[0]) {
+        w.write(0, 1);
+        w.write(0b000000, 6); // CONSTANT
+        w.write(0, 1);
+        w.write_signed(block[0], 16);
+        return;
+    }
+
+    // Pick the fixed predictor order with the cheapest residual
+    let best = (0..=4usize)
+        .filter(|&order| order < block.len())
+        .map(|order| {
+            let residual = fixed_residual(block, order);
+            let (bits, params) = best_partitioning(&residual, block.len(), order);
+            (order as u64 * 16 + bits, order, residual, params)
+        })
+        .min_by_key(|candidate| candidate.0);
+
+    let verbatim_bits = block.len() as u64 * 16;
+    match best {
+        Some((bits, order, residual, (partition_order, params))) if bits < verbatim_bits => {
+            w.write(0, 1);
+            w.write(0b001000 | order as u64, 6); // FIXED
+            w.write(0, 1);
+            for &s in &block[..order] {
+                w.write_signed(s, 16);
+            }
+            w.write(0b00, 2); // Rice, 4-bit parameters
+            w.write(partition_order as u64, 4);
+            write_residual(w, &residual, block.len(), order, partition_order, &params);
+        }
+        _ => {
+            w.write(0, 1);
+            w.write(0b000001, 6); // VERBATIM
+            w.write(0, 1);
+            for &s in block {
+                w.write_signed(s, 16);
+            }
+        }
+    }
+}
+
+/// Prediction error of the fixed polynomial predictor of `order`, for every
+/// sample after the warm-up
+fn fixed_residual(block: &[i32], order: usize) -> Vec<i32> {
+    (order..block.len())
+        .map(|i| {
+            let x = |k: usize| block[i - k];
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(r: i32) -> u32 {
+    ((r << 1) ^ (r >> 31)) as u32
+}
+
+/// Residual slices per partition. The first partition is short by the
+/// predictor's warm-up samples.
+fn partitions(
+    residual: &[i32],
+    block_len: usize,
+    order: usize,
+    partition_order: u32,
+) -> Vec<&[i32]> {
+    let n = block_len >> partition_order;
+    let mut out = Vec::with_capacity(1 << partition_order);
+    let mut start = 0;
+    for p in 0..(1usize << partition_order) {
+        let len = if p == 0 { n - order } else { n };
+        out.push(&residual[start..start + len]);
+        start += len;
+    }
+    out
+}
+
+/// Cheapest Rice parameter for a partition and its cost in bits
+fn best_rice_param(partition: &[i32]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAM)
+        .map(|k| {
+            let bits: u64 = partition
+                .iter()
+                .map(|&r| (zigzag(r) >> k) as u64 + 1 + k as u64)
+                .sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+/// Best partition order for this residual: (total bits, (order, params))
+fn best_partitioning(residual: &[i32], block_len: usize, order: usize) -> (u64, (u32, Vec<u32>)) {
+    let mut best: Option<(u64, (u32, Vec<u32>))> = None;
+
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        // Each partition must hold a whole share of the block, and the first
+        // must be longer than the warm-up
+        if !block_len.is_multiple_of(1 << partition_order)
+            || (block_len >> partition_order) <= order
+        {
+            break;
+        }
+
+        let mut bits = 2 + 4;
+        let mut params = Vec::new();
+        for partition in partitions(residual, block_len, order, partition_order) {
+            let (k, cost) = best_rice_param(partition);
+            bits += 4 + cost;
+            params.push(k);
+        }
+
+        if best.as_ref().is_none_or(|(b, _)| bits < *b) {
+            best = Some((bits, (partition_order, params)));
+        }
+    }
+
+    best.unwrap_or((u64::MAX, (0, Vec::new())))
+}
+
+fn write_residual(
+    w: &mut BitWriter,
+    residual: &[i32],
+    block_len: usize,
+    order: usize,
+    partition_order: u32,
+    params: &[u32],
+) {
+    for (partition, &k) in partitions(residual, block_len, order, partition_order)
+        .into_iter()
+        .zip(params)
+    {
+        w.write(k as u64, 4);
+        for &r in partition {
+            let u = zigzag(r);
+            w.write_unary(u >> k);
+            w.write((u & ((1 << k) - 1)) as u64, k);
+        }
+    }
+}
+
+/// FLAC's UTF-8-like variable length frame number
+fn write_utf8_number(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.write(n, 8);
+        return;
+    }
+    let extra = match n {
+        0..=0x7FF => 1,
+        0x800..=0xFFFF => 2,
+        0x1_0000..=0x1F_FFFF => 3,
+        0x20_0000..=0x3FF_FFFF => 4,
+        0x400_0000..=0x7FFF_FFFF => 5,
+        _ => 6,
+    };
+    let lead_ones = (0xFF00u16 >> (extra + 1)) as u8;
+    let lead = lead_ones as u64 | (n >> (6 * extra));
+    w.write(lead, 8);
+    for i in (0..extra).rev() {
+        w.write(0x80 | ((n >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// MSB-first bit writer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    n_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1);
+            self.n_bits += 1;
+            if self.n_bits == 8 {
+                self.bytes.push(self.acc as u8);
+                self.acc = 0;
+                self.n_bits = 0;
+            }
+        }
+    }
+
+    fn write_signed(&mut self, value: i32, bits: u32) {
+        self.write(value as u64 & ((1 << bits) - 1), bits);
+    }
+
+    /// `n` zeros then a one
+    fn write_unary(&mut self, n: u32) {
+        for _ in 0..n {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// Zero-pad to the next byte boundary
+    fn align(&mut self) {
+        if self.n_bits > 0 {
+            self.write(0, 8 - self.n_bits);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Opus in Ogg, 20 ms VoIP frames
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "opus")]
+const OPUS_BITRATE: i32 = 24_000;
+
+#[cfg(feature = "opus")]
+fn samples_to_opus(samples: &[f32]) -> Result<Vec<u8>, String> {
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Bitrate, Channels, SampleRate};
+    use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+    const FRAME: usize = SAMPLE_RATE as usize / 50;
+    // Ogg Opus granule positions always count 48kHz samples
+    const GRANULE_SCALE: u64 = 48000 / SAMPLE_RATE as u64;
+    const SERIAL: u32 = 0x5750_4153;
+
+    let mut encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip)
+        .map_err(|e| e.to_string())?;
+    encoder
+        .set_bitrate(Bitrate::BitsPerSecond(OPUS_BITRATE))
+        .map_err(|e| e.to_string())?;
+    let pre_skip = encoder.lookahead().map_err(|e| e.to_string())? as u64 * GRANULE_SCALE;
+
+    let mut out = Vec::new();
+    let mut writer = PacketWriter::new(&mut out);
+    let io_err = |e: std::io::Error| e.to_string();
+
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+    head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // mapping family
+    writer
+        .write_packet(head, SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(io_err)?;
+
+    let vendor = b"whisper-paste";
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+    writer
+        .write_packet(tags, SERIAL, PacketWriteEndInfo::EndPage, 0)
+        .map_err(io_err)?;
+
+    let n_frames = samples.len().div_ceil(FRAME).max(1);
+    let mut packet = [0u8; 4000];
+    let mut frame = [0f32; FRAME];
+    for i in 0..n_frames {
+        let chunk = &samples[(i * FRAME).min(samples.len())..((i + 1) * FRAME).min(samples.len())];
+        frame.fill(0.0);
+        frame[..chunk.len()].copy_from_slice(chunk);
+
+        let len = encoder
+            .encode_float(&frame, &mut packet)
+            .map_err(|e| e.to_string())?;
+
+        let last = i + 1 == n_frames;
+        let granule = if last {
+            pre_skip + samples.len() as u64 * GRANULE_SCALE
+        } else {
+            pre_skip + ((i + 1) * FRAME) as u64 * GRANULE_SCALE
+        };
+        let end = if last {
+            PacketWriteEndInfo::EndStream
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        writer
+            .write_packet(packet[..len].to_vec(), SERIAL, end, granule)
+            .map_err(io_err)?;
+    }
+
+    drop(writer);
+    Ok(out)
+}
+
+#[cfg(not(feature = "opus"))]
+fn samples_to_opus(_samples: &[f32]) -> Result<Vec<u8>, String> {
+    Err("this build has no Opus support; rebuild with `--features opus`".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speechy(n: usize) -> Vec<f32> {
+        (0..n)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                0.3 * (t * 220.0 * std::f32::consts::TAU).sin()
+                    + 0.1 * (t * 1330.0 * std::f32::consts::TAU).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn flac_has_streaminfo() {
+        let samples = speechy(10_000);
+        let flac = samples_to_flac(&samples);
+
+        assert_eq!(&flac[0..4], b"fLaC");
+        // Last-metadata-block flag + STREAMINFO type, length 34
+        assert_eq!(&flac[4..8], &[0x80, 0, 0, 34]);
+        // Sample rate (20 bits) | channels-1 (3) | bps-1 (5) | total samples (36)
+        let info = u64::from_be_bytes(flac[18..26].try_into().unwrap());
+        assert_eq!(info >> 44, 16000);
+        assert_eq!((info >> 41) & 0b111, 0);
+        assert_eq!((info >> 36) & 0b11111, 15);
+        assert_eq!(info & 0xF_FFFF_FFFF, 10_000);
+        // First frame follows the 42-byte header with a sync code
+        assert_eq!(&flac[42..44], &[0xFF, 0xF8]);
+    }
+
+    #[test]
+    fn flac_decodes_back_to_the_input() {
+        // A frame of each kind: fixed-predictor, a constant one of silence,
+        // verbatim noise, and a short last frame
+        let mut samples = speechy(FLAC_BLOCK_SIZE);
+        samples.extend(vec![0.0; FLAC_BLOCK_SIZE]);
+        let mut seed = 1u32;
+        samples.extend((0..FLAC_BLOCK_SIZE).map(|_| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 16) as f32 / 32768.0 - 1.0
+        }));
+        samples.extend(speechy(1000));
+
+        assert_eq!(flac_round_trip(&samples), to_i16(&samples));
+    }
+
+    /// Encode, then decode with an independent decoder
+    fn flac_round_trip(samples: &[f32]) -> Vec<i32> {
+        let flac = encode(samples, Codec::Flac).unwrap();
+        let mut reader = claxon::FlacReader::new(&flac[..]).unwrap();
+        assert_eq!(reader.streaminfo().sample_rate, SAMPLE_RATE);
+        // A total of 0 reads as "unknown"
+        let total = Some(samples.len() as u64).filter(|&n| n > 0);
+        assert_eq!(reader.streaminfo().samples, total);
+        reader.samples().map(|s| s.unwrap()).collect()
+    }
+
+    #[test]
+    fn flac_round_trips_edge_cases() {
+        let full_scale: Vec<f32> = (0..FLAC_BLOCK_SIZE)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let clipped: Vec<f32> = speechy(FLAC_BLOCK_SIZE + 7)
+            .iter()
+            .map(|s| s * 8.0)
+            .collect();
+        let cases: [(&str, Vec<f32>); 8] = [
+            ("empty", Vec::new()),
+            ("one sample", vec![0.25]),
+            ("constant", vec![-0.5; FLAC_BLOCK_SIZE * 2]),
+            ("constant, short last block", vec![0.5; FLAC_BLOCK_SIZE + 3]),
+            ("full scale square", full_scale),
+            ("clipped", clipped),
+            ("short only", speechy(FLAC_BLOCK_SIZE - 1)),
+            ("block plus one", speechy(FLAC_BLOCK_SIZE + 1)),
+        ];
+        for (name, samples) in cases {
+            assert_eq!(flac_round_trip(&samples), to_i16(&samples), "{name}");
+        }
+    }
+
+    #[test]
+    fn flac_is_smaller_than_wav() {
+        let samples = speechy(SAMPLE_RATE as usize * 3);
+        let wav = audio::samples_to_wav(&samples);
+        let flac = samples_to_flac(&samples);
+        assert!(
+            flac.len() < wav.len() * 2 / 3,
+            "flac {} vs wav {}",
+            flac.len(),
+            wav.len()
+        );
+    }
+
+    #[test]
+    fn flac_silence_uses_constant_subframes() {
+        let flac = samples_to_flac(&vec![0.0; FLAC_BLOCK_SIZE * 4]);
+        // header + 4 tiny frames
+        assert!(flac.len() < 42 + 4 * 16);
+    }
+
+    #[test]
+    fn fixed_residual_of_a_ramp_vanishes_at_order_two() {
+        let ramp: Vec<i32> = (0..32).map(|i| i * 3 + 7).collect();
+        assert!(fixed_residual(&ramp, 2).iter().all(|&r| r == 0));
+        assert!(fixed_residual(&ramp, 1).iter().all(|&r| r == 3));
+    }
+
+    #[test]
+    fn utf8_frame_numbers() {
+        let encode = |n| {
+            let mut w = BitWriter::default();
+            write_utf8_number(&mut w, n);
+            w.bytes
+        };
+        assert_eq!(encode(0x41), vec![0x41]);
+        assert_eq!(encode(0x7FF), vec![0xDF, 0xBF]);
+        assert_eq!(encode(0x800), vec![0xE0, 0xA0, 0x80]);
+    }
+
+    #[test]
+    fn codec_names_and_types() {
+        assert_eq!(Codec::parse("FLAC"), Some(Codec::Flac));
+        assert_eq!(Codec::parse("ogg"), Some(Codec::Opus));
+        assert_eq!(Codec::parse("mp3"), None);
+        assert_eq!(Codec::Flac.mime(), "audio/flac");
+        assert_eq!(Codec::Opus.file_name(), "audio.ogg");
+        // About 13 minutes of WAV fits in 25 MB
+        assert_eq!(Codec::Wav.max_samples(25_000_000) / 16000 / 60, 13);
+    }
+}
@@ -0,0 +1,221 @@
+//! An ordered chain of backends: when one fails with a retryable error
+//! (network, timeout, rate limit, 5xx), or refuses because the monthly
+//! budget is spent, the same audio goes to the next.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::transcribe::{
+    LiveSession, Partial, Task, TranscribeError, Transcript, TranscriptionBackend,
+};
+
+pub struct FallbackBackend {
+    /// (label for logs and history, backend), the configured one first
+    chain: Vec<(String, Arc<dyn TranscriptionBackend>)>,
+}
+
+impl FallbackBackend {
+    pub fn new(chain: Vec<(String, Arc<dyn TranscriptionBackend>)>) -> Self {
+        assert!(!chain.is_empty(), "fallback chain needs a backend");
+        Self { chain }
+    }
+}
+
+#[async_trait]
+impl TranscriptionBackend for FallbackBackend {
+    fn name(&self) -> &str {
+        self.chain[0].1.name()
+    }
+
+    /// Failing to translate isn't retryable, so the chain never gets past
+    /// the first backend
+    fn can_translate(&self) -> bool {
+        self.chain[0].1.can_translate()
+    }
+
+    async fn transcribe(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.stream(Task::Transcribe, samples, &|_| {}).await
+    }
+
+    async fn translate(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.stream(Task::Translate, samples, &|_| {}).await
+    }
+
+    /// Each backend gets one attempt; `transcribe_with_retry` retries the
+    /// whole chain if the last one fails too
+    async fn stream(
+        &self,
+        task: Task,
+        samples: &[f32],
+        partial: Partial<'_>,
+    ) -> Result<Transcript, TranscribeError> {
+        let mut chain = self.chain.iter().enumerate().peekable();
+        while let Some((i, (label, backend))) = chain.next() {
+            match backend.stream(task, samples, partial).await {
+                Ok(mut transcript) => {
+                    if i > 0 {
+                        transcript.fallback = Some(label.clone());
+                    }
+                    return Ok(transcript);
+                }
+                Err(e) if moves_on(&e) => match chain.peek() {
+                    Some((_, (next, _))) => eprintln!("{label} failed ({e}); trying {next}"),
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("fallback chain is never empty")
+    }
+
+    /// The strictest limit, so chunks fit whichever backend ends up used
+    fn max_samples(&self) -> Option<usize> {
+        self.chain
+            .iter()
+            .filter_map(|(_, backend)| backend.max_samples())
+            .min()
+    }
+
+    /// Only the configured backend streams live; if its session fails, the
+    /// recording goes through the chain afterwards
+    fn live(&self, task: Task, partial: Arc<dyn Fn(&str) + Send + Sync>) -> Option<LiveSession> {
+        self.chain[0].1.live(task, partial)
+    }
+
+    async fn warm_up(&self) {
+        self.chain[0].1.warm_up().await;
+    }
+}
+
+/// Errors the next backend in the chain may well not run into
+fn moves_on(e: &TranscribeError) -> bool {
+    e.is_retryable() || matches!(e, TranscribeError::BudgetExceeded { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails with `error` (if any), otherwise answers with its name
+    struct Fake {
+        name: &'static str,
+        error: Option<fn() -> TranscribeError>,
+        max_samples: Option<usize>,
+        calls: AtomicUsize,
+    }
+
+    impl Fake {
+        fn new(name: &'static str, error: Option<fn() -> TranscribeError>) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                error,
+                max_samples: None,
+                calls: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl TranscriptionBackend for Fake {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn transcribe(&self, _samples: &[f32]) -> Result<Transcript, TranscribeError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.error {
+                Some(error) => Err(error()),
+                None => Ok(Transcript {
+                    text: self.name.to_string(),
+                    ..Default::default()
+                }),
+            }
+        }
+
+        fn max_samples(&self) -> Option<usize> {
+            self.max_samples
+        }
+    }
+
+    fn chain(backends: &[Arc<Fake>]) -> FallbackBackend {
+        FallbackBackend::new(
+            backends
+                .iter()
+                .map(|b| {
+                    (
+                        b.name.to_string(),
+                        b.clone() as Arc<dyn TranscriptionBackend>,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn timeout() -> TranscribeError {
+        TranscribeError::Network("operation timed out".into())
+    }
+
+    fn unauthorized() -> TranscribeError {
+        TranscribeError::Auth("bad key".into())
+    }
+
+    #[tokio::test]
+    async fn first_success_wins() {
+        let backends = [Fake::new("server", None), Fake::new("openai", None)];
+        let transcript = chain(&backends).transcribe(&[]).await.unwrap();
+        assert_eq!(transcript.text, "server");
+        assert_eq!(transcript.fallback, None);
+        assert_eq!(backends[1].calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn retryable_errors_fall_through_in_order() {
+        let backends = [
+            Fake::new("server", Some(timeout)),
+            Fake::new("openai", Some(timeout)),
+            Fake::new("local", None),
+        ];
+        let transcript = chain(&backends).transcribe(&[]).await.unwrap();
+        assert_eq!(transcript.text, "local");
+        assert_eq!(transcript.fallback.as_deref(), Some("local"));
+
+        let backends = [
+            Fake::new("server", Some(timeout)),
+            Fake::new("openai", Some(timeout)),
+        ];
+        let err = chain(&backends).transcribe(&[]).await.unwrap_err();
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn other_errors_stop_the_chain() {
+        let backends = [
+            Fake::new("server", Some(unauthorized)),
+            Fake::new("openai", None),
+        ];
+        let err = chain(&backends).transcribe(&[]).await.unwrap_err();
+        assert!(matches!(err, TranscribeError::Auth(_)));
+        assert_eq!(backends[1].calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn strictest_size_limit_applies() {
+        let limited = |name, max_samples| {
+            Arc::new(Fake {
+                name,
+                error: None,
+                max_samples,
+                calls: AtomicUsize::new(0),
+            })
+        };
+        let backends = [
+            limited("openai", Some(400)),
+            limited("local", None),
+            limited("azure", Some(300)),
+        ];
+        assert_eq!(chain(&backends).max_samples(), Some(300));
+        assert_eq!(chain(&backends[1..2]).max_samples(), None);
+    }
+}
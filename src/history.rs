@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::transcribe::Transcript;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// One finished transcription, stored as a JSON line in `history.jsonl`
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    /// Unix seconds when the audio was recorded
+    pub timestamp: u64,
+    pub backend: String,
+    /// True if the text came from the offline queue rather than live dictation
+    #[serde(default)]
+    pub from_queue: bool,
+    /// Text plus segments, words and language when the backend provides them
+    #[serde(flatten)]
+    pub transcript: Transcript,
+}
+
+pub fn history_path() -> PathBuf {
+    crate::config::data_dir().join("history.jsonl")
+}
+
+pub fn append(entry: &Entry) -> Result<(), String> {
+    append_to(&history_path(), entry)
+}
+
+fn append_to(path: &Path, entry: &Entry) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("failed to create data dir: {e}"))?;
+    }
+
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open history: {e}"))?;
+    writeln!(file, "{line}").map_err(|e| format!("failed to write history: {e}"))
+}
+
+pub fn now_unix() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_writes_json_lines() {
+        let tmp = std::env::temp_dir().join("whisper-paste-history-test");
+        std::fs::remove_dir_all(&tmp).ok();
+        let path = tmp.join("history.jsonl");
+
+        for text in ["first", "second"] {
+            let entry = Entry {
+                timestamp: 1,
+                backend: "openai".to_string(),
+                from_queue: false,
+                transcript: Transcript {
+                    text: text.to_string(),
+                    ..Default::default()
+                },
+            };
+            append_to(&path, &entry).unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<Entry> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].transcript.text, "second");
+        assert!(!content.contains("segments"));
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -0,0 +1,129 @@
+//! Control commands for a running instance, e.g. `whisper-paste cancel` bound
+//! to a key in the window manager. Each connection to the socket carries one
+//! command line and gets one reply line back.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::overlay::AppState;
+
+pub fn socket_path() -> PathBuf {
+    crate::config::data_dir().join("control.sock")
+}
+
+/// Answer control commands on a background thread
+pub fn serve(state: Arc<AppState>) -> Result<(), String> {
+    serve_at(&socket_path(), state)
+}
+
+/// Send a command to the running instance and return its reply
+pub fn send(command: &str) -> Result<String, String> {
+    send_to(&socket_path(), command)
+}
+
+fn handle(state: &AppState, command: &str) -> String {
+    match command {
+        "cancel" => {
+            if state.cancel() {
+                "cancelled".to_string()
+            } else {
+                "nothing to cancel".to_string()
+            }
+        }
+        other => format!("error: unknown command: {other}"),
+    }
+}
+
+#[cfg(unix)]
+fn serve_at(path: &Path, state: Arc<AppState>) -> Result<(), String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("failed to create data dir: {e}"))?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!(
+                "another instance is listening on {}",
+                path.display()
+            ));
+        }
+        // Left behind by an instance that didn't exit cleanly
+        std::fs::remove_file(path).map_err(|e| format!("failed to remove stale socket: {e}"))?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| format!("failed to bind {}: {e}", path.display()))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            stream
+                .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+                .ok();
+            let mut line = String::new();
+            if BufReader::new(&stream).read_line(&mut line).is_err() {
+                continue;
+            }
+            let reply = handle(&state, line.trim());
+            writeln!(stream, "{reply}").ok();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn send_to(path: &Path, command: &str) -> Result<String, String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream =
+        UnixStream::connect(path).map_err(|e| format!("whisper-paste is not running ({e})"))?;
+    writeln!(stream, "{command}").map_err(|e| format!("failed to send command: {e}"))?;
+
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| format!("failed to read reply: {e}"))?;
+    Ok(reply.trim().to_string())
+}
+
+#[cfg(not(unix))]
+fn serve_at(_path: &Path, _state: Arc<AppState>) -> Result<(), String> {
+    Err("control commands are only supported on Unix".to_string())
+}
+
+#[cfg(not(unix))]
+fn send_to(_path: &Path, _command: &str) -> Result<String, String> {
+    Err("control commands are only supported on Unix".to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::overlay::{STATUS_IDLE, STATUS_RECORDING};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn cancel_over_socket() {
+        let tmp = std::env::temp_dir().join("whisper-paste-ipc-test");
+        std::fs::remove_dir_all(&tmp).ok();
+        let path = tmp.join("control.sock");
+
+        let state = Arc::new(AppState::new());
+        serve_at(&path, state.clone()).unwrap();
+        assert!(serve_at(&path, state.clone()).is_err());
+
+        assert_eq!(send_to(&path, "cancel").unwrap(), "nothing to cancel");
+
+        state.status.store(STATUS_RECORDING, Ordering::SeqCst);
+        assert_eq!(send_to(&path, "cancel").unwrap(), "cancelled");
+        assert!(state.cancel_signal.load(Ordering::SeqCst));
+        assert!(state.stop_signal.load(Ordering::SeqCst));
+
+        state.status.store(STATUS_IDLE, Ordering::SeqCst);
+        assert!(send_to(&path, "pause").unwrap().starts_with("error:"));
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+use crate::transcribe::{
+    RequestParams, Segment, Task, TranscribeError, Transcript, TranscriptionBackend,
+};
+
+/// Offline whisper.cpp inference on the CPU. Works on the 16kHz mono samples
+/// directly, so no WAV encoding and no network.
+pub struct LocalBackend {
+    ctx: Arc<WhisperContext>,
+    threads: usize,
+    params: RequestParams,
+}
+
+impl LocalBackend {
+    pub fn new(model_path: &Path, threads: usize, params: RequestParams) -> Result<Self, String> {
+        let path = model_path
+            .to_str()
+            .ok_or_else(|| format!("model path is not valid UTF-8: {}", model_path.display()))?;
+        let ctx = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+            .map_err(|e| format!("failed to load model {}: {e}", model_path.display()))?;
+
+        Ok(Self {
+            ctx: Arc::new(ctx),
+            threads,
+            params,
+        })
+    }
+}
+
+#[async_trait]
+impl TranscriptionBackend for LocalBackend {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn can_translate(&self) -> bool {
+        true
+    }
+
+    async fn transcribe(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.infer(Task::Transcribe, samples).await
+    }
+
+    async fn translate(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.infer(Task::Translate, samples).await
+    }
+}
+
+impl LocalBackend {
+    async fn infer(&self, task: Task, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        let ctx = self.ctx.clone();
+        let threads = self.threads;
+        let params = self.params.clone();
+        let samples = samples.to_vec();
+
+        // Inference takes seconds of CPU; keep it off the async workers
+        tokio::task::spawn_blocking(move || run(&ctx, threads, &params, task, &samples))
+            .await
+            .map_err(|e| format!("local inference panicked: {e}"))
+            .and_then(|r| r)
+            .map_err(TranscribeError::Other)
+    }
+}
+
+fn run(
+    ctx: &WhisperContext,
+    threads: usize,
+    request: &RequestParams,
+    task: Task,
+    samples: &[f32],
+) -> Result<Transcript, String> {
+    let mut state = ctx
+        .create_state()
+        .map_err(|e| format!("failed to create whisper state: {e}"))?;
+
+    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    params.set_n_threads(threads as i32);
+    // "auto" lets whisper.cpp detect the spoken language
+    params.set_language(Some(request.language.as_deref().unwrap_or("auto")));
+    if let Some(ref prompt) = request.prompt {
+        params.set_initial_prompt(prompt);
+    }
+    if let Some(temperature) = request.temperature {
+        params.set_temperature(temperature);
+    }
+    params.set_translate(task == Task::Translate);
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+
+    state
+        .full(params, samples)
+        .map_err(|e| format!("local inference failed: {e}"))?;
+
+    let n_segments = state
+        .full_n_segments()
+        .map_err(|e| format!("local inference failed: {e}"))?;
+
+    let read_err = |i, e| format!("failed to read segment {i}: {e}");
+    let mut transcript = Transcript {
+        duration: Some(samples.len() as f32 / 16000.0),
+        ..Default::default()
+    };
+    for i in 0..n_segments {
+        let text = state.full_get_segment_text(i).map_err(|e| read_err(i, e))?;
+        // whisper.cpp timestamps are in centiseconds
+        let start = state.full_get_segment_t0(i).map_err(|e| read_err(i, e))? as f32 / 100.0;
+        let end = state.full_get_segment_t1(i).map_err(|e| read_err(i, e))? as f32 / 100.0;
+
+        transcript.text.push_str(&text);
+        transcript.segments.push(Segment {
+            start,
+            end,
+            text,
+            avg_logprob: None,
+            no_speech_prob: None,
+        });
+    }
+
+    transcript.text = transcript.text.trim().to_string();
+    Ok(transcript)
+}
@@ -1,18 +1,40 @@
 mod audio;
+mod chunk;
 mod config;
+mod encode;
+mod fallback;
+mod history;
+mod ipc;
+#[cfg(feature = "local")]
+mod local;
 mod overlay;
 mod paste;
+mod queue;
+mod realtime;
+mod sse;
 mod transcribe;
+mod usage;
+mod ws;
 
+use std::future::Future;
+use std::ops::Range;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use device_query::{DeviceQuery, DeviceState, Keycode};
-use overlay::{AppState, STATUS_IDLE, STATUS_RECORDING, STATUS_RESULT, STATUS_TRANSCRIBING};
+use overlay::{
+    AppState, STATUS_ERROR, STATUS_IDLE, STATUS_RECORDING, STATUS_RESULT, STATUS_TRANSCRIBING,
+};
+use tokio::task::{AbortHandle, JoinHandle};
+use transcribe::{Task, TranscribeError, Transcript, TranscriptionBackend};
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let overrides = config::Overrides::take_from_args(&mut args).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
 
     // CLI commands
     if args.len() > 1 {
@@ -38,19 +60,60 @@ fn main() {
                 }
                 return;
             }
+            "cancel" => {
+                match ipc::send("cancel") {
+                    Ok(reply) if !reply.starts_with("error:") => println!("{reply}"),
+                    Ok(reply) => {
+                        eprintln!("Error: {}", reply.trim_start_matches("error: "));
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {e}");
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+            "devices" => {
+                run_devices();
+                return;
+            }
+            "usage" => {
+                run_usage(&overrides);
+                return;
+            }
+            "doctor" => {
+                run_doctor(&overrides);
+                return;
+            }
+            "queue" => {
+                run_queue_command(args.get(2).map(String::as_str), &overrides);
+                return;
+            }
             "--help" | "-h" => {
                 println!("whisper-paste - voice to text, pasted anywhere");
                 println!();
                 println!("Usage:");
                 println!("  whisper-paste              Start with overlay UI");
                 println!("  whisper-paste --no-ui      Start without overlay (terminal only)");
-                println!("  whisper-paste --setup      Interactive setup (save API key)");
+                println!("  whisper-paste --setup      Interactive setup (OpenAI or Azure)");
                 println!("  whisper-paste --api-key K  Save API key directly");
+                println!("  whisper-paste cancel       Cancel the current dictation");
+                println!("  whisper-paste doctor       Check the connection to the API");
+                println!("  whisper-paste devices      List microphones (for input_device)");
+                println!("  whisper-paste usage        Audio sent and estimated cost this month");
+                println!("  whisper-paste queue        List recordings that failed to transcribe");
+                println!("  whisper-paste queue retry  Transcribe queued recordings now");
                 println!("  whisper-paste --help       Show this help");
+                println!();
+                println!("Options (override the config file for this run):");
+                println!("  --language CODE            Spoken language (ISO-639-1, or auto)");
+                println!("  --prompt TEXT              Vocabulary hint, e.g. names and jargon");
+                println!("  --temperature T            Sampling temperature, 0.0 to 1.0");
                 return;
             }
             "--no-ui" => {
-                run_headless();
+                run_headless(&overrides);
                 return;
             }
             other => {
@@ -61,16 +124,185 @@ fn main() {
         }
     }
 
-    run_with_overlay();
+    run_with_overlay(&overrides);
+}
+
+fn run_devices() {
+    let devices = audio::input_devices().unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+    if devices.is_empty() {
+        println!("No input devices found.");
+        return;
+    }
+
+    for device in &devices {
+        let default = if device.is_default { "  (default)" } else { "" };
+        println!("{}{default}", device.name);
+        for config in &device.configs {
+            println!("    {config}");
+        }
+    }
+    println!();
+    println!(
+        "Set input_device in {} to a name, or part of one.",
+        config::config_path().display()
+    );
+}
+
+fn run_usage(overrides: &config::Overrides) {
+    let cfg = config::load_config(overrides);
+    let now = history::now_unix();
+    let (year, month) = usage::year_month(now);
+    let records = usage::load();
+    let totals = usage::month_totals(&records, &cfg.prices, now);
+
+    println!("Usage for {year}-{month:02} (UTC)");
+    if totals.is_empty() {
+        println!("  Nothing sent to a paid API yet.");
+    }
+    let mut minutes = 0.0;
+    let mut unpriced = false;
+    for ((backend, model), t) in &totals {
+        minutes += t.seconds / 60.0;
+        let cost = match t.cost {
+            Some(cost) => format!("${cost:.2}"),
+            None => {
+                unpriced = true;
+                "no price".to_string()
+            }
+        };
+        println!(
+            "  {backend:<10} {model:<24} {:>4} request(s) {:>8.1} min  {cost:>9}",
+            t.requests,
+            t.seconds / 60.0
+        );
+    }
+    let spent = usage::month_cost(&records, &cfg.prices, now);
+    println!("  Total {minutes:.1} min, ${spent:.2}");
+    if unpriced {
+        println!("  Add [prices] to the config for models without one.");
+    }
+    if let Some(budget) = cfg.budget {
+        println!(
+            "  Budget ${:.2}/month, {:.0}% used",
+            budget.monthly,
+            if budget.monthly > 0.0 {
+                spent / budget.monthly * 100.0
+            } else {
+                100.0
+            }
+        );
+    }
 }
 
-fn run_with_overlay() {
-    let cfg = config::load_config();
+fn run_doctor(overrides: &config::Overrides) {
+    let cfg = config::load_config(overrides);
+    println!("Config:     {}", config::config_path().display());
+
+    let backend = transcribe::backend_from_config(&cfg).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+    println!("Backend:    {}", backend.name());
+
+    let Some(url) = transcribe::service_url(&cfg) else {
+        println!("Endpoint:   none (offline)");
+        return;
+    };
+    println!("Endpoint:   {url}");
+    println!("Proxy:      {}", transcribe::describe_proxy(&cfg));
+    for (i, root) in transcribe::describe_roots(&cfg).iter().enumerate() {
+        let label = if i == 0 { "TLS roots:" } else { "" };
+        println!("{label:<11} {root}");
+    }
+
+    // Any answer at all means DNS, the proxy and TLS worked
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to create tokio runtime");
+    let client = transcribe::http_client(&cfg).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+    match rt.block_on(client.head(url).send()) {
+        Ok(resp) => println!("Connection: ok (HTTP {})", resp.status().as_u16()),
+        Err(e) => {
+            // reqwest's own message is just "error sending request"; the
+            // cause (certificate, proxy, DNS) is further down the chain
+            let mut message = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(cause) = source {
+                message.push_str(&format!(": {cause}"));
+                source = cause.source();
+            }
+            println!("Connection: failed: {message}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_queue_command(sub: Option<&str>, overrides: &config::Overrides) {
+    match sub {
+        None | Some("list") => {
+            let items = queue::list();
+            if items.is_empty() {
+                println!("Queue is empty.");
+                return;
+            }
+            println!(
+                "{} recording(s) in {}",
+                items.len(),
+                queue::queue_dir().display()
+            );
+            for item in &items {
+                println!(
+                    "  {}  {:>6.1}s  {} attempt(s)  {}",
+                    item.id, item.meta.duration_secs, item.meta.attempts, item.meta.last_error
+                );
+            }
+            if items.iter().any(|item| item.meta.parked) {
+                println!("Recordings that failed with an error retrying won't fix are only");
+                println!("sent again by `whisper-paste queue retry`.");
+            }
+        }
+        Some("retry") => {
+            let cfg = config::load_config(overrides);
+            let backend = transcribe::backend_from_config(&cfg).unwrap_or_else(|e| {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            });
+            let rt = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("failed to create tokio runtime");
+
+            let summary = rt.block_on(queue::process(&*backend, true, &|| true));
+            println!(
+                "{} transcribed, {} failed, {} still queued",
+                summary.done, summary.failed, summary.remaining
+            );
+            if summary.done > 0 {
+                println!("Results saved to {}", history::history_path().display());
+            }
+        }
+        Some(other) => {
+            eprintln!("Unknown queue command: {other}");
+            eprintln!("Usage: whisper-paste queue [list|retry]");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_with_overlay(overrides: &config::Overrides) {
+    let cfg = config::load_config(overrides);
     let state = Arc::new(AppState::new());
 
     println!("whisper-paste running (with overlay)");
-    println!("  Hotkey: Ctrl+Shift+R");
-    println!("  Ctrl+C to quit");
+    println!("  Hotkey: Ctrl+Shift+R (Ctrl+Shift+E to translate to English)");
+    println!("  Escape to cancel, Ctrl+C to quit");
 
     // Spawn hotkey + recording logic on background thread
     let state_clone = state.clone();
@@ -100,18 +332,25 @@ fn run_with_overlay() {
     .expect("failed to run overlay");
 }
 
-fn run_headless() {
-    let cfg = config::load_config();
+fn run_headless(overrides: &config::Overrides) {
+    let cfg = config::load_config(overrides);
     let state = Arc::new(AppState::new());
 
     println!("whisper-paste running (no UI)");
-    println!("  Hotkey: Ctrl+Shift+R");
-    println!("  Ctrl+C to quit");
+    println!("  Hotkey: Ctrl+Shift+R (Ctrl+Shift+E to translate to English)");
+    println!("  Escape to cancel, Ctrl+C to quit");
 
     hotkey_loop(cfg, state);
 }
 
+const QUEUE_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
 fn hotkey_loop(cfg: config::Config, state: Arc<AppState>) {
+    let backend = transcribe::backend_from_config(&cfg).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    });
+
     let rt = Arc::new(
         tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -119,111 +358,108 @@ fn hotkey_loop(cfg: config::Config, state: Arc<AppState>) {
             .expect("failed to create tokio runtime"),
     );
 
+    // Retry the offline queue in the background. Only while idle: the queue
+    // writes to the clipboard, which would race a paste in progress.
+    {
+        let state = state.clone();
+        let backend = backend.clone();
+        let rt = rt.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(QUEUE_RETRY_INTERVAL);
+            let idle = || {
+                let status = state.status.load(Ordering::Relaxed);
+                status != STATUS_RECORDING && status != STATUS_TRANSCRIBING
+            };
+            if !idle() {
+                continue;
+            }
+            if queue::list().iter().any(|item| !item.meta.parked) {
+                let summary = rt.block_on(queue::process(&*backend, false, &idle));
+                if summary.done > 0 {
+                    println!(
+                        "Queue: {} recording(s) transcribed and copied to the clipboard",
+                        summary.done
+                    );
+                }
+            }
+        });
+    }
+
+    if let Err(e) = ipc::serve(state.clone()) {
+        eprintln!("control socket unavailable: {e}");
+    }
+
+    let settings = RecordSettings {
+        incremental: cfg.incremental,
+        input_device: cfg.input_device.clone(),
+        auto_stop: cfg.auto_stop,
+        trim_padding: cfg.trim_padding,
+    };
+
     let device_state = DeviceState::new();
     let mut hotkey_held = false;
+    let mut escape_held = false;
     let mut last_toggle = Instant::now();
 
     loop {
         let keys = device_state.get_keys();
-        let hotkey_pressed = keys.contains(&Keycode::LControl)
-            && keys.contains(&Keycode::LShift)
-            && keys.contains(&Keycode::R);
+        let ctrl_shift = keys.contains(&Keycode::LControl) && keys.contains(&Keycode::LShift);
+        let record_pressed = ctrl_shift && keys.contains(&Keycode::R);
+        let translate_pressed = ctrl_shift && keys.contains(&Keycode::E);
+        let hotkey_pressed = record_pressed || translate_pressed;
+        let escape_pressed = keys.contains(&Keycode::Escape);
+
+        // Escape only means something while busy; otherwise it belongs to
+        // whatever app has focus
+        if escape_pressed && !escape_held {
+            state.cancel();
+        }
+        escape_held = escape_pressed;
 
         if hotkey_pressed && !hotkey_held && last_toggle.elapsed() > Duration::from_millis(500) {
             hotkey_held = true;
             last_toggle = Instant::now();
 
             let status = state.status.load(Ordering::Relaxed);
+            let idle = status == STATUS_IDLE || status == STATUS_RESULT || status == STATUS_ERROR;
+            let refusal = if idle { budget_refusal(&cfg) } else { None };
 
             if status == STATUS_TRANSCRIBING {
                 // Still transcribing, ignore
-            } else if status == STATUS_IDLE || status == STATUS_RESULT {
-                // Start recording (also from result state)
+            } else if idle && translate_pressed && !backend.can_translate() {
+                // The recording would only fail afterwards and end up queued
+                eprintln!("The {} backend cannot translate", backend.name());
+                *state.last_error.lock().unwrap() =
+                    format!("Translation not supported by {}", backend.name());
+                state.status.store(STATUS_ERROR, Ordering::SeqCst);
+            } else if let Some(e) = refusal {
+                // Recording would only end in a refusal
+                eprintln!("{e}");
+                *state.last_error.lock().unwrap() = e.summary();
+                state.status.store(STATUS_ERROR, Ordering::SeqCst);
+            } else if idle {
+                // Start recording (also from result/error state)
+                let task = if translate_pressed {
+                    Task::Translate
+                } else {
+                    Task::Transcribe
+                };
+                state
+                    .translate
+                    .store(task == Task::Translate, Ordering::SeqCst);
+                state.cancel_signal.store(false, Ordering::SeqCst);
                 state.status.store(STATUS_RECORDING, Ordering::SeqCst);
                 state.stop_signal.store(false, Ordering::SeqCst);
                 // Clear old waveform
                 state.waveform.lock().unwrap().clear();
 
                 let state_c = state.clone();
-                let api_key = cfg.api_key.clone();
-                let model = cfg.model.clone();
+                let backend = backend.clone();
                 let rt = rt.clone();
+                let settings = settings.clone();
 
                 std::thread::spawn(move || {
-                    println!("Recording...");
-
-                    let waveform = Arc::new(std::sync::Mutex::new(Vec::new()));
-
-                    // Share waveform with overlay
-                    {
-                        let wf = waveform.clone();
-                        let state_wf = state_c.clone();
-                        std::thread::spawn(move || {
-                            // Periodically copy waveform data to overlay state
-                            while state_wf.status.load(Ordering::Relaxed) == STATUS_RECORDING {
-                                {
-                                    let src = wf.lock().unwrap();
-                                    let mut dst = state_wf.waveform.lock().unwrap();
-                                    dst.clear();
-                                    dst.extend_from_slice(&src);
-                                }
-                                std::thread::sleep(Duration::from_millis(50));
-                            }
-                        });
-                    }
-
-                    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
-                    let stop_clone = stop.clone();
-
-                    // Monitor the app state stop_signal
-                    let state_stop = state_c.clone();
-                    std::thread::spawn(move || {
-                        while !state_stop.stop_signal.load(Ordering::SeqCst) {
-                            std::thread::sleep(Duration::from_millis(30));
-                        }
-                        stop_clone.store(true, Ordering::SeqCst);
-                    });
-
-                    match audio::record_until_stopped(stop, Some(waveform)) {
-                        Ok(samples) => {
-                            if samples.is_empty() {
-                                println!("(no audio captured)");
-                                state_c.status.store(STATUS_IDLE, Ordering::SeqCst);
-                                return;
-                            }
-
-                            state_c.status.store(STATUS_TRANSCRIBING, Ordering::SeqCst);
-                            println!("Transcribing...");
-                            let wav = audio::samples_to_wav(&samples);
-
-                            match rt.block_on(transcribe::transcribe(&api_key, &model, wav)) {
-                                Ok(text) => {
-                                    if text.is_empty() {
-                                        println!("(no speech detected)");
-                                        state_c.status.store(STATUS_IDLE, Ordering::SeqCst);
-                                    } else {
-                                        println!("Result: {}", text);
-                                        // Store result for overlay display
-                                        *state_c.last_result.lock().unwrap() = text.clone();
-                                        // Try to paste
-                                        if let Err(e) = paste::paste_text(&text) {
-                                            eprintln!("paste error: {e}");
-                                        }
-                                        // Show result in overlay
-                                        state_c.status.store(STATUS_RESULT, Ordering::SeqCst);
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("transcription error: {e}");
-                                    state_c.status.store(STATUS_IDLE, Ordering::SeqCst);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("recording error: {e}");
-                            state_c.status.store(STATUS_IDLE, Ordering::SeqCst);
-                        }
-                    }
+                    dictate(state_c, backend, rt, task, settings);
                 });
             } else if status == STATUS_RECORDING {
                 // Stop recording
@@ -239,3 +475,411 @@ fn hotkey_loop(cfg: config::Config, state: Arc<AppState>) {
         std::thread::sleep(Duration::from_millis(30));
     }
 }
+
+/// Set when the monthly budget is spent and remote transcription is refused
+fn budget_refusal(cfg: &config::Config) -> Option<TranscribeError> {
+    let budget = cfg.budget?;
+    if budget.over != config::OverBudget::Refuse {
+        return None;
+    }
+    // A free backend anywhere in the chain can still do the work
+    let all_paid = std::iter::once(cfg)
+        .chain(&cfg.fallbacks)
+        .all(|c| transcribe::billed_model(c).is_some());
+    if !all_paid {
+        return None;
+    }
+    usage::over_budget(&budget, &cfg.prices)
+}
+
+/// A segment being transcribed, with its start offset in seconds and its
+/// audio, kept for the offline queue in case it fails
+type PendingPart = (
+    f32,
+    Vec<f32>,
+    JoinHandle<Result<Transcript, TranscribeError>>,
+);
+
+/// How a dictation records and what it sends, from the config
+#[derive(Clone)]
+struct RecordSettings {
+    incremental: bool,
+    input_device: Option<String>,
+    auto_stop: Option<config::AutoStop>,
+    /// Seconds of silence kept around speech; `None` sends audio untrimmed
+    trim_padding: Option<f32>,
+}
+
+impl RecordSettings {
+    /// The part of `samples` worth sending, or `None` if there is no speech
+    fn speech(&self, samples: &[f32]) -> Option<Range<usize>> {
+        match self.trim_padding {
+            Some(padding) => audio::speech_bounds(samples, padding),
+            None => (!samples.is_empty()).then_some(0..samples.len()),
+        }
+    }
+}
+
+/// Start transcribing one part of a dictation. Parts too long for a single
+/// upload are sent in chunks, with progress shown on the overlay.
+fn spawn_part(
+    rt: &tokio::runtime::Runtime,
+    backend: &Arc<dyn TranscriptionBackend>,
+    state: &Arc<AppState>,
+    task: Task,
+    samples: Vec<f32>,
+) -> JoinHandle<Result<Transcript, TranscribeError>> {
+    let backend = backend.clone();
+    let state = state.clone();
+    rt.spawn(async move {
+        let progress = |chunk, total| {
+            println!("Chunk {chunk}/{total}");
+            *state.chunk_progress.lock().unwrap() = Some((chunk, total));
+        };
+        let partial = |text: &str| *state.partial_text.lock().unwrap() = text.to_string();
+        chunk::transcribe_chunked(&*backend, task, &samples, &progress, &partial).await
+    })
+}
+
+/// One dictation: record until stopped, transcribe, paste.
+fn dictate(
+    state: Arc<AppState>,
+    backend: Arc<dyn TranscriptionBackend>,
+    rt: Arc<tokio::runtime::Runtime>,
+    task: Task,
+    settings: RecordSettings,
+) {
+    match task {
+        Task::Transcribe => println!("Recording..."),
+        Task::Translate => println!("Recording (translate to English)..."),
+    }
+
+    let waveform = Arc::new(std::sync::Mutex::new(Vec::new()));
+    *state.chunk_progress.lock().unwrap() = None;
+    state.partial_text.lock().unwrap().clear();
+    *state.audio_warning.lock().unwrap() = None;
+
+    // Share waveform with overlay
+    {
+        let wf = waveform.clone();
+        let state_wf = state.clone();
+        std::thread::spawn(move || {
+            // Periodically copy waveform data to overlay state
+            while state_wf.status.load(Ordering::Relaxed) == STATUS_RECORDING {
+                {
+                    let src = wf.lock().unwrap();
+                    let mut dst = state_wf.waveform.lock().unwrap();
+                    dst.clear();
+                    dst.extend_from_slice(&src);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+    }
+
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let stop_clone = stop.clone();
+
+    // Monitor the app state stop_signal
+    let state_stop = state.clone();
+    std::thread::spawn(move || {
+        while !state_stop.stop_signal.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(30));
+        }
+        stop_clone.store(true, Ordering::SeqCst);
+    });
+
+    // Hands-free: stop once speech is followed by enough silence, or at the
+    // hard limit
+    let vad = settings
+        .auto_stop
+        .map(|_| Arc::new(std::sync::Mutex::new(audio::Vad::new())));
+    if let (Some(auto_stop), Some(vad)) = (settings.auto_stop, vad.clone()) {
+        let state = state.clone();
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            while state.status.load(Ordering::Relaxed) == STATUS_RECORDING
+                && !state.stop_signal.load(Ordering::SeqCst)
+            {
+                let silence = vad.lock().unwrap().trailing_silence();
+                let progress = silence.map(|s| s / auto_stop.silence.as_secs_f32());
+                if progress.is_some_and(|p| p >= 1.0) {
+                    println!("Silence, stopping");
+                    state.stop_signal.store(true, Ordering::SeqCst);
+                } else if started.elapsed() >= auto_stop.max_duration {
+                    println!(
+                        "Reached max_duration ({}s), stopping",
+                        auto_stop.max_duration.as_secs_f32()
+                    );
+                    state.stop_signal.store(true, Ordering::SeqCst);
+                }
+                *state.auto_stop_progress.lock().unwrap() = progress;
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            *state.auto_stop_progress.lock().unwrap() = None;
+        });
+    }
+
+    // Backends with a live session transcribe while we record
+    let partial_state = state.clone();
+    let partial = Arc::new(move |text: &str| {
+        *partial_state.partial_text.lock().unwrap() = text.to_string();
+    });
+    let (frames_out, live) = match backend.live(task, partial) {
+        Some(session) => (Some(session.frames), Some(rt.spawn(session.result))),
+        None => {
+            let backend = backend.clone();
+            rt.spawn(async move { backend.warm_up().await });
+            (None, None)
+        }
+    };
+
+    // Segments cut at pauses are transcribed in the background while
+    // recording continues; results are joined in order at the end
+    let mut pending: Vec<PendingPart> = Vec::new();
+    // Samples handed over in segments so far
+    let mut cut = 0;
+    let started = history::now_unix();
+    let mut on_segment = |segment: Vec<f32>| {
+        let offset = cut;
+        cut += segment.len();
+        let Some(speech) = settings.speech(&segment) else {
+            println!("Segment without speech skipped");
+            return;
+        };
+        println!(
+            "Segment {} ({:.1}s) sent",
+            pending.len() + 1,
+            speech.len() as f32 / 16000.0
+        );
+        let offset = (offset + speech.start) as f32 / 16000.0;
+        let segment = segment[speech].to_vec();
+        let handle = spawn_part(&rt, &backend, &state, task, segment.clone());
+        pending.push((offset, segment, handle));
+    };
+    let on_segment: Option<&mut dyn FnMut(Vec<f32>)> = if settings.incremental && live.is_none() {
+        Some(&mut on_segment)
+    } else {
+        None
+    };
+
+    let tail = match audio::record_until_stopped(
+        settings.input_device.as_deref(),
+        stop,
+        Some(waveform),
+        frames_out,
+        vad,
+        on_segment,
+        &|warning| *state.audio_warning.lock().unwrap() = Some(warning.to_string()),
+    ) {
+        Ok(tail) => tail,
+        // Segments cut before the failure are still transcribed (or queued)
+        Err(e) if !pending.is_empty() => {
+            eprintln!("recording error: {e}; keeping the segments recorded so far");
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("recording error: {e}");
+            if let Some(handle) = live {
+                handle.abort();
+            }
+            state.status.store(STATUS_IDLE, Ordering::SeqCst);
+            return;
+        }
+    };
+
+    // Everything still running in the background, for a cancel to stop
+    let mut aborts: Vec<AbortHandle> = pending.iter().map(|(_, _, h)| h.abort_handle()).collect();
+    aborts.extend(live.as_ref().map(JoinHandle::abort_handle));
+
+    if state.cancel_signal.load(Ordering::SeqCst) {
+        cancelled(&state, &aborts);
+        return;
+    }
+
+    if pending.is_empty() && tail.is_empty() {
+        println!("(no audio captured)");
+        state.status.store(STATUS_IDLE, Ordering::SeqCst);
+        return;
+    }
+
+    // A live session has already heard everything, but its final commit
+    // and answer aren't needed (or paid for) when nobody spoke
+    if live.is_some() && settings.speech(&tail).is_none() {
+        for handle in &aborts {
+            handle.abort();
+        }
+        println!("(no speech detected)");
+        state.status.store(STATUS_IDLE, Ordering::SeqCst);
+        return;
+    }
+
+    let tail_offset = cut as f32 / 16000.0;
+
+    state.status.store(STATUS_TRANSCRIBING, Ordering::SeqCst);
+    println!("Transcribing ({})...", backend.name());
+
+    // A live session has most of the text already. If it broke, the
+    // recording is sent the usual way instead.
+    let live_result = match live.map(|handle| until_cancelled(&rt, &state, handle)) {
+        None => None,
+        Some(None) => {
+            cancelled(&state, &aborts);
+            return;
+        }
+        Some(Some(Ok(Ok(transcript)))) => Some(transcript),
+        Some(Some(Ok(Err(e)))) => {
+            eprintln!("live session failed ({e}); sending the recording instead");
+            None
+        }
+        Some(Some(Err(e))) => {
+            eprintln!("live session task failed ({e}); sending the recording instead");
+            None
+        }
+    };
+    if live_result.is_some() {
+        pending.clear();
+    } else if let Some(speech) = settings.speech(&tail) {
+        let offset = tail_offset + speech.start as f32 / 16000.0;
+        let speech = tail[speech].to_vec();
+        let handle = spawn_part(&rt, &backend, &state, task, speech.clone());
+        aborts.push(handle.abort_handle());
+        pending.push((offset, speech, handle));
+    } else if pending.is_empty() {
+        // Nothing but silence; no need to ask the API
+        println!("(no speech detected)");
+        state.status.store(STATUS_IDLE, Ordering::SeqCst);
+        return;
+    }
+
+    let outcome = until_cancelled(&rt, &state, async {
+        match live_result {
+            Some(transcript) => (transcript, Vec::new(), None),
+            None => join_parts(pending).await,
+        }
+    });
+    let Some((transcript, failed, error)) = outcome else {
+        cancelled(&state, &aborts);
+        return;
+    };
+
+    // What did get transcribed is pasted even if other parts failed
+    let text = transcript.text.clone();
+    if !text.is_empty() {
+        println!("Result: {}", text);
+        if let Some(ref fallback) = transcript.fallback {
+            println!("(transcribed by fallback {fallback})");
+        }
+        *state.fallback.lock().unwrap() = transcript.fallback.clone();
+        let entry = history::Entry {
+            timestamp: started,
+            backend: transcript
+                .fallback
+                .clone()
+                .unwrap_or_else(|| backend.name().to_string()),
+            from_queue: false,
+            transcript,
+        };
+        if let Err(e) = history::append(&entry) {
+            eprintln!("history error: {e}");
+        }
+        // Store result for overlay display
+        *state.last_result.lock().unwrap() = text.clone();
+        // Try to paste
+        if let Err(e) = paste::paste_text(&text) {
+            eprintln!("paste error: {e}");
+        }
+    }
+
+    match error {
+        None if text.is_empty() => {
+            println!("(no speech detected)");
+            state.status.store(STATUS_IDLE, Ordering::SeqCst);
+        }
+        // Show result in overlay
+        None => state.status.store(STATUS_RESULT, Ordering::SeqCst),
+        Some(e) => {
+            eprintln!("transcription error: {e}");
+            let mut summary = e.summary();
+            // Keep the audio of the failed parts so it can be retried
+            // instead of lost
+            let mut queued = 0;
+            for samples in &failed {
+                match queue::enqueue(samples, task, &e) {
+                    Ok(path) => {
+                        println!("Recording saved to {}", path.display());
+                        queued += 1;
+                    }
+                    Err(qe) => eprintln!("queue error: {qe}"),
+                }
+            }
+            if queued > 0 && !text.is_empty() {
+                summary.push_str(&format!(" ({queued} part(s) queued)"));
+            } else if queued > 0 {
+                summary.push_str(" (queued)");
+            }
+            *state.last_error.lock().unwrap() = summary;
+            state.status.store(STATUS_ERROR, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Wait for every part in order: (the joined transcripts, the audio of the
+/// parts that failed, the first error). Once one fails, parts still running
+/// are stopped and count as failed too, since the backend is unlikely to do
+/// better for them.
+async fn join_parts(
+    pending: Vec<PendingPart>,
+) -> (Transcript, Vec<Vec<f32>>, Option<TranscribeError>) {
+    let mut parts = Vec::with_capacity(pending.len());
+    let mut failed = Vec::new();
+    let mut error = None;
+    for (offset, samples, handle) in pending {
+        if error.is_some() && !handle.is_finished() {
+            handle.abort();
+            failed.push(samples);
+            continue;
+        }
+        let part = handle
+            .await
+            .map_err(|e| TranscribeError::Other(format!("transcription task failed: {e}")))
+            .and_then(|result| result);
+        match part {
+            Ok(part) => parts.push((offset, part)),
+            Err(e) => {
+                failed.push(samples);
+                error.get_or_insert(e);
+            }
+        }
+    }
+    (Transcript::join(parts), failed, error)
+}
+
+/// Run `work` to completion, or until the user cancels (`None`)
+fn until_cancelled<T>(
+    rt: &tokio::runtime::Runtime,
+    state: &AppState,
+    work: impl Future<Output = T>,
+) -> Option<T> {
+    rt.block_on(async {
+        tokio::select! {
+            out = work => Some(out),
+            _ = async {
+                while !state.cancel_signal.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(30)).await;
+                }
+            } => None,
+        }
+    })
+}
+
+/// Drop a cancelled dictation: stop its requests and go back to idle
+/// without pasting or queueing anything
+fn cancelled(state: &AppState, aborts: &[AbortHandle]) {
+    for handle in aborts {
+        handle.abort();
+    }
+    println!("Cancelled.");
+    state.partial_text.lock().unwrap().clear();
+    state.status.store(STATUS_IDLE, Ordering::SeqCst);
+}
@@ -1,14 +1,30 @@
 use eframe::egui;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 
 pub struct AppState {
-    /// 0 = idle, 1 = recording, 2 = transcribing, 3 = result
+    /// 0 = idle, 1 = recording, 2 = transcribing, 3 = result, 4 = error
     pub status: AtomicU8,
     pub waveform: Mutex<Vec<f32>>,
     pub stop_signal: std::sync::atomic::AtomicBool,
+    /// Discard the current recording or transcription instead of pasting it
+    pub cancel_signal: AtomicBool,
     /// Last transcription result for display
     pub last_result: Mutex<String>,
+    /// Short description of the last failure for display
+    pub last_error: Mutex<String>,
+    /// Current recording is translated to English rather than transcribed
+    pub translate: AtomicBool,
+    /// (chunk, total) while a recording too long for one upload is sent in chunks
+    pub chunk_progress: Mutex<Option<(usize, usize)>>,
+    /// Text streamed in so far by the request being transcribed
+    pub partial_text: Mutex<String>,
+    /// The fallback backend that produced the last result, if one had to
+    pub fallback: Mutex<Option<String>>,
+    /// Shown instead of "Recording" after the microphone dropped out
+    pub audio_warning: Mutex<Option<String>>,
+    /// How far the hands-free silence timeout has run, 0.0 to 1.0
+    pub auto_stop_progress: Mutex<Option<f32>>,
 }
 
 impl AppState {
@@ -17,15 +33,36 @@ impl AppState {
             status: AtomicU8::new(0),
             waveform: Mutex::new(Vec::new()),
             stop_signal: std::sync::atomic::AtomicBool::new(false),
+            cancel_signal: AtomicBool::new(false),
             last_result: Mutex::new(String::new()),
+            last_error: Mutex::new(String::new()),
+            translate: AtomicBool::new(false),
+            chunk_progress: Mutex::new(None),
+            partial_text: Mutex::new(String::new()),
+            fallback: Mutex::new(None),
+            audio_warning: Mutex::new(None),
+            auto_stop_progress: Mutex::new(None),
         }
     }
+
+    /// Abandon the recording or transcription in progress. Returns false if
+    /// there was nothing to cancel.
+    pub fn cancel(&self) -> bool {
+        let status = self.status.load(Ordering::SeqCst);
+        if status != STATUS_RECORDING && status != STATUS_TRANSCRIBING {
+            return false;
+        }
+        self.cancel_signal.store(true, Ordering::SeqCst);
+        self.stop_signal.store(true, Ordering::SeqCst);
+        true
+    }
 }
 
 pub const STATUS_IDLE: u8 = 0;
 pub const STATUS_RECORDING: u8 = 1;
 pub const STATUS_TRANSCRIBING: u8 = 2;
 pub const STATUS_RESULT: u8 = 3;
+pub const STATUS_ERROR: u8 = 4;
 
 pub struct OverlayApp {
     pub state: Arc<AppState>,
@@ -59,6 +96,14 @@ const TEXT_PRIMARY: egui::Color32 = egui::Color32::from_rgb(240, 240, 245);
 const RED: egui::Color32 = egui::Color32::from_rgb(255, 69, 58);
 const GREEN: egui::Color32 = egui::Color32::from_rgb(48, 209, 88);
 const BLUE: egui::Color32 = egui::Color32::from_rgb(10, 132, 255);
+const ORANGE: egui::Color32 = egui::Color32::from_rgb(255, 159, 10);
+
+/// Characters of streamed text that fit beside the dots
+const PARTIAL_CHARS: usize = 40;
+/// Short pauses between words don't bring up the countdown ring
+const COUNTDOWN_FROM: f32 = 0.25;
+/// The ring circles the recording dot, which gets room for it
+const RING_RADIUS: f32 = 8.0;
 
 const ROUNDING: f32 = 18.0;
 const IDLE_HIDE_DELAY: f64 = 3.0;
@@ -92,7 +137,7 @@ impl eframe::App for OverlayApp {
         if status != self.last_status {
             if status == STATUS_IDLE {
                 self.idle_since = Some(std::time::Instant::now());
-            } else if status == STATUS_RESULT {
+            } else if status == STATUS_RESULT || status == STATUS_ERROR {
                 // Show result for 6 seconds before fading
                 self.idle_since = Some(std::time::Instant::now());
                 self.target_opacity = 1.0;
@@ -109,7 +154,7 @@ impl eframe::App for OverlayApp {
         }
 
         // Determine target opacity
-        let hide_delay = if status == STATUS_RESULT {
+        let hide_delay = if status == STATUS_RESULT || status == STATUS_ERROR {
             6.0 // show result longer
         } else {
             IDLE_HIDE_DELAY
@@ -121,10 +166,12 @@ impl eframe::App for OverlayApp {
             false
         };
 
-        if should_hide && (status == STATUS_IDLE || status == STATUS_RESULT) {
+        if should_hide
+            && (status == STATUS_IDLE || status == STATUS_RESULT || status == STATUS_ERROR)
+        {
             self.target_opacity = 0.0;
-            // Transition result -> idle when faded
-            if status == STATUS_RESULT && self.opacity < 0.05 {
+            // Transition result/error -> idle when faded
+            if status != STATUS_IDLE && self.opacity < 0.05 {
                 self.state.status.store(STATUS_IDLE, Ordering::SeqCst);
             }
         }
@@ -225,11 +272,14 @@ impl eframe::App for OverlayApp {
                                 draw_recording(ui, &self.state, self.phase, op);
                             }
                             STATUS_TRANSCRIBING => {
-                                draw_transcribing(ui, self.phase, op);
+                                draw_transcribing(ui, &self.state, self.phase, op);
                             }
                             STATUS_RESULT => {
                                 draw_result(ui, &self.state, op, &mut self.idle_since);
                             }
+                            STATUS_ERROR => {
+                                draw_error(ui, &self.state, op, &mut self.idle_since);
+                            }
                             _ => {
                                 draw_idle(ui, &mut self.idle_since, op);
                             }
@@ -281,7 +331,7 @@ fn draw_idle(ui: &mut egui::Ui, idle_since: &mut Option<std::time::Instant>, op:
     ui.add_space(6.0);
 
     ui.label(
-        egui::RichText::new("Ready  |  Ctrl+Shift+R to record")
+        egui::RichText::new("Ready  |  Ctrl+Shift+R record, Ctrl+Shift+E translate")
             .color(with_opacity(TEXT_DIM, op))
             .size(12.0),
     );
@@ -297,11 +347,18 @@ fn draw_recording(ui: &mut egui::Ui, state: &Arc<AppState>, phase: f32, op: f32)
     let red_a = (pulse * 255.0 * op) as u8;
     let pulsing_red = egui::Color32::from_rgba_unmultiplied(255, 69, 58, red_a);
 
-    let (dot_rect, _) = ui.allocate_exact_size(egui::vec2(10.0, 10.0), egui::Sense::hover());
+    let ring_size = 2.0 * RING_RADIUS + 2.0;
+    let (dot_rect, _) =
+        ui.allocate_exact_size(egui::vec2(ring_size, ring_size), egui::Sense::hover());
     ui.painter().circle_filled(dot_rect.center(), 4.5, pulsing_red);
     ui.painter().circle_filled(dot_rect.center(), 3.0, with_opacity(RED, op));
+    if let Some(progress) = *state.auto_stop_progress.lock().unwrap() {
+        if progress >= COUNTDOWN_FROM {
+            draw_countdown_ring(ui, dot_rect.center(), progress, op);
+        }
+    }
 
-    ui.add_space(8.0);
+    ui.add_space(4.0);
 
     // Waveform
     let waveform_width = 140.0;
@@ -352,11 +409,20 @@ fn draw_recording(ui: &mut egui::Ui, state: &Arc<AppState>, phase: f32, op: f32)
 
     ui.add_space(8.0);
 
-    ui.label(
-        egui::RichText::new("Recording")
-            .color(with_opacity(TEXT_PRIMARY, op))
-            .size(12.0),
-    );
+    match *state.audio_warning.lock().unwrap() {
+        Some(ref warning) => ui.label(
+            egui::RichText::new(warning)
+                .color(with_opacity(ORANGE, op))
+                .size(12.0),
+        ),
+        None => ui.label(
+            egui::RichText::new("Recording")
+                .color(with_opacity(TEXT_PRIMARY, op))
+                .size(12.0),
+        ),
+    };
+
+    draw_mode_badge(ui, state, op);
 
     ui.add_space(6.0);
 
@@ -375,6 +441,53 @@ fn draw_recording(ui: &mut egui::Ui, state: &Arc<AppState>, phase: f32, op: f32)
     if stop_resp.clicked() {
         state.stop_signal.store(true, std::sync::atomic::Ordering::SeqCst);
     }
+
+    draw_cancel_button(ui, state, op);
+}
+
+/// Ring around the recording dot that runs out as the hands-free silence
+/// timeout approaches
+fn draw_countdown_ring(ui: &mut egui::Ui, center: egui::Pos2, progress: f32, op: f32) {
+    let stroke = |color| egui::Stroke::new(1.5, color);
+    let painter = ui.painter();
+    painter.circle_stroke(center, RING_RADIUS, stroke(bar_bg(op)));
+
+    let remaining = (1.0 - progress).clamp(0.0, 1.0);
+    let steps = (40.0 * remaining).ceil() as usize;
+    if steps == 0 {
+        return;
+    }
+    let points: Vec<egui::Pos2> = (0..=steps)
+        .map(|i| {
+            let angle = -std::f32::consts::FRAC_PI_2
+                + std::f32::consts::TAU * remaining * i as f32 / steps as f32;
+            center + RING_RADIUS * egui::vec2(angle.cos(), angle.sin())
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        stroke(with_opacity(TEXT_PRIMARY, op)),
+    ));
+}
+
+/// Discards the recording or transcription; Escape does the same
+fn draw_cancel_button(ui: &mut egui::Ui, state: &Arc<AppState>, op: f32) {
+    ui.add_space(4.0);
+
+    let cancel_resp = ui.add(
+        egui::Button::new(
+            egui::RichText::new(" Cancel ")
+                .color(with_opacity(TEXT_PRIMARY, op))
+                .size(10.0),
+        )
+        .fill(bar_bg(op * 0.5))
+        .rounding(8.0)
+        .stroke(egui::Stroke::NONE),
+    );
+
+    if cancel_resp.clicked() {
+        state.cancel();
+    }
 }
 
 fn draw_result(
@@ -394,10 +507,16 @@ fn draw_result(
 
     // Show truncated result text
     let result = state.last_result.lock().unwrap();
-    let display_text = if result.len() > 50 {
-        format!("{}...", &result[..50])
+    // Leave room for the fallback badge when there is one
+    let max_len = if state.fallback.lock().unwrap().is_some() {
+        38
     } else {
-        result.clone()
+        50
+    };
+    // Cut on a character boundary; byte offsets can land inside one
+    let display_text = match result.char_indices().nth(max_len) {
+        Some((end, _)) => format!("{}...", &result[..end]),
+        None => result.clone(),
     };
 
     ui.label(
@@ -406,6 +525,8 @@ fn draw_result(
             .size(11.0),
     );
 
+    draw_fallback_badge(ui, state, op);
+
     ui.add_space(6.0);
 
     // Copy button
@@ -432,7 +553,78 @@ fn draw_result(
     }
 }
 
-fn draw_transcribing(ui: &mut egui::Ui, phase: f32, op: f32) {
+fn draw_error(
+    ui: &mut egui::Ui,
+    state: &Arc<AppState>,
+    op: f32,
+    idle_since: &mut Option<std::time::Instant>,
+) {
+    ui.label(
+        egui::RichText::new("!")
+            .color(with_opacity(RED, op))
+            .size(14.0)
+            .strong(),
+    );
+
+    ui.add_space(6.0);
+
+    let error = state.last_error.lock().unwrap();
+    ui.label(
+        egui::RichText::new(error.as_str())
+            .color(with_opacity(TEXT_PRIMARY, op))
+            .size(11.0),
+    );
+
+    // Reset idle timer on hover
+    if ui.ui_contains_pointer() {
+        *idle_since = Some(std::time::Instant::now());
+    }
+}
+
+/// Small "→ EN" pill while in translate mode
+fn draw_mode_badge(ui: &mut egui::Ui, state: &Arc<AppState>, op: f32) {
+    if !state.translate.load(Ordering::Relaxed) {
+        return;
+    }
+
+    ui.add_space(4.0);
+    egui::Frame::none()
+        .fill(with_opacity(BLUE, op * 0.25))
+        .rounding(6.0)
+        .inner_margin(egui::Margin::symmetric(5.0, 1.0))
+        .show(ui, |ui: &mut egui::Ui| {
+            ui.label(
+                egui::RichText::new("→ EN")
+                    .color(with_opacity(BLUE, op))
+                    .size(10.0),
+            );
+        });
+}
+
+/// Dim "via local" pill when a fallback backend produced the result
+fn draw_fallback_badge(ui: &mut egui::Ui, state: &Arc<AppState>, op: f32) {
+    let fallback = state.fallback.lock().unwrap();
+    let Some(ref name) = *fallback else {
+        return;
+    };
+
+    ui.add_space(4.0);
+    egui::Frame::none()
+        .fill(bar_bg(op * 0.3))
+        .rounding(6.0)
+        .inner_margin(egui::Margin::symmetric(5.0, 1.0))
+        .show(ui, |ui: &mut egui::Ui| {
+            ui.label(
+                egui::RichText::new(format!("via {name}"))
+                    .color(with_opacity(TEXT_DIM, op))
+                    .size(10.0),
+            );
+        })
+        .response
+        .on_hover_text("The configured backend failed; a fallback transcribed this");
+}
+
+fn draw_transcribing(ui: &mut egui::Ui, state: &Arc<AppState>, phase: f32, op: f32) {
     // Bouncing dots
     let n_dots = 3;
     let dot_spacing = 10.0;
@@ -450,9 +642,42 @@ fn draw_transcribing(ui: &mut egui::Ui, phase: f32, op: f32) {
 
     ui.add_space(8.0);
 
-    ui.label(
-        egui::RichText::new("Transcribing...")
-            .color(with_opacity(BLUE, op))
-            .size(12.0),
-    );
+    let verb = if state.translate.load(Ordering::Relaxed) {
+        "Translating"
+    } else {
+        "Transcribing"
+    };
+    let text = match *state.chunk_progress.lock().unwrap() {
+        Some((chunk, total)) => format!("{verb} chunk {chunk}/{total}..."),
+        None => format!("{verb}..."),
+    };
+
+    // Streaming backends show the text as it grows, keeping the newest words
+    let partial = state.partial_text.lock().unwrap();
+    if partial.is_empty() {
+        ui.label(
+            egui::RichText::new(text)
+                .color(with_opacity(BLUE, op))
+                .size(12.0),
+        );
+    } else {
+        let tail_start = partial
+            .char_indices()
+            .rev()
+            .nth(PARTIAL_CHARS - 1)
+            .map_or(0, |(i, _)| i);
+        let shown = if tail_start > 0 {
+            format!("...{}", &partial[tail_start..])
+        } else {
+            partial.clone()
+        };
+        ui.label(
+            egui::RichText::new(shown)
+                .color(with_opacity(TEXT_PRIMARY, op))
+                .size(11.0),
+        );
+    }
+
+    draw_mode_badge(ui, state, op);
+    draw_cancel_button(ui, state, op);
 }
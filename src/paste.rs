@@ -2,9 +2,14 @@ use arboard::Clipboard;
 use std::thread;
 use std::time::Duration;
 
-pub fn paste_text(text: &str) -> Result<(), String> {
+/// Put text on the clipboard without pasting it anywhere
+pub fn copy_text(text: &str) -> Result<(), String> {
     let mut clip = Clipboard::new().map_err(|e| format!("clipboard error: {e}"))?;
-    clip.set_text(text).map_err(|e| format!("clipboard set error: {e}"))?;
+    clip.set_text(text).map_err(|e| format!("clipboard set error: {e}"))
+}
+
+pub fn paste_text(text: &str) -> Result<(), String> {
+    copy_text(text)?;
 
     thread::sleep(Duration::from_millis(100));
 
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::audio;
+use crate::chunk;
+use crate::history;
+use crate::paste;
+use crate::transcribe::{Task, TranscribeError, TranscriptionBackend};
+
+/// Sidecar metadata stored next to each queued WAV as `<id>.json`
+#[derive(Serialize, Deserialize)]
+pub struct Meta {
+    /// Unix seconds when the audio was recorded
+    pub created: u64,
+    pub duration_secs: f32,
+    pub attempts: u32,
+    pub last_error: String,
+    /// Translation recordings are retried as translations
+    #[serde(default)]
+    pub task: Task,
+    /// Failed in a way retrying won't fix by itself (a rejected key, a 4xx),
+    /// so only `whisper-paste queue retry` sends it again
+    #[serde(default)]
+    pub parked: bool,
+}
+
+pub struct Item {
+    pub id: String,
+    pub meta: Meta,
+}
+
+/// Outcome of one pass over the queue
+#[derive(Default)]
+pub struct Summary {
+    pub done: usize,
+    pub failed: usize,
+    pub remaining: usize,
+}
+
+pub fn queue_dir() -> PathBuf {
+    crate::config::data_dir().join("queue")
+}
+
+/// Save audio that failed to transcribe so it can be retried later
+pub fn enqueue(samples: &[f32], task: Task, error: &TranscribeError) -> Result<PathBuf, String> {
+    enqueue_in(&queue_dir(), samples, task, error)
+}
+
+fn enqueue_in(
+    dir: &Path,
+    samples: &[f32],
+    task: Task,
+    error: &TranscribeError,
+) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("failed to create queue dir: {e}"))?;
+
+    let created = history::now_unix();
+    // Millisecond ids sort in recording order; parts of one dictation can
+    // fail within the same millisecond and get a suffix
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let id = (0..)
+        .map(|n| match n {
+            0 => format!("{millis}"),
+            n => format!("{millis}-{n}"),
+        })
+        .find(|id| !dir.join(format!("{id}.json")).exists())
+        .unwrap_or_default();
+
+    let meta = Meta {
+        created,
+        duration_secs: samples.len() as f32 / 16000.0,
+        attempts: 1,
+        last_error: error.to_string(),
+        task,
+        parked: needs_attention(error),
+    };
+
+    let wav_path = dir.join(format!("{id}.wav"));
+    std::fs::write(&wav_path, audio::samples_to_wav(samples))
+        .map_err(|e| format!("failed to write {}: {e}", wav_path.display()))?;
+    write_meta(dir, &id, &meta)?;
+
+    Ok(wav_path)
+}
+
+/// Errors that will keep happening until someone changes something
+fn needs_attention(error: &TranscribeError) -> bool {
+    !(error.is_retryable() || matches!(error, TranscribeError::BudgetExceeded { .. }))
+}
+
+fn write_meta(dir: &Path, id: &str, meta: &Meta) -> Result<(), String> {
+    let path = dir.join(format!("{id}.json"));
+    let json = serde_json::to_string_pretty(meta).map_err(|e| e.to_string())?;
+    std::fs::write(&path, json).map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+/// Queued recordings, oldest first
+pub fn list() -> Vec<Item> {
+    list_in(&queue_dir())
+}
+
+fn list_in(dir: &Path) -> Vec<Item> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut items: Vec<Item> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|p| {
+            let id = p.file_stem()?.to_str()?.to_string();
+            if !dir.join(format!("{id}.wav")).exists() {
+                return None;
+            }
+            let meta = serde_json::from_str(&std::fs::read_to_string(&p).ok()?).ok()?;
+            Some(Item { id, meta })
+        })
+        .collect();
+
+    items.sort_by(|a, b| a.id.cmp(&b.id));
+    items
+}
+
+fn load_samples(dir: &Path, id: &str) -> Result<Vec<f32>, String> {
+    let path = dir.join(format!("{id}.wav"));
+    let wav =
+        std::fs::read(&path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    audio::wav_to_samples(&wav)
+}
+
+fn remove(dir: &Path, id: &str) {
+    std::fs::remove_file(dir.join(format!("{id}.wav"))).ok();
+    std::fs::remove_file(dir.join(format!("{id}.json"))).ok();
+}
+
+/// Try every queued recording once; parked ones only when `manual` (the
+/// user asked for a retry). Results go to history and the clipboard, never
+/// pasted, since focus has long moved elsewhere. Stops at the first
+/// retryable failure: the backend is still unreachable. `idle` is asked
+/// before each recording and before touching the clipboard, so a dictation
+/// started meanwhile doesn't race its paste against the queue.
+pub async fn process(
+    backend: &dyn TranscriptionBackend,
+    manual: bool,
+    idle: &dyn Fn() -> bool,
+) -> Summary {
+    process_in(&queue_dir(), backend, manual, idle).await
+}
+
+async fn process_in(
+    dir: &Path,
+    backend: &dyn TranscriptionBackend,
+    manual: bool,
+    idle: &dyn Fn() -> bool,
+) -> Summary {
+    let items = list_in(dir);
+    let mut summary = Summary {
+        remaining: items.len(),
+        ..Default::default()
+    };
+
+    for mut item in items {
+        if item.meta.parked && !manual {
+            continue;
+        }
+        if !idle() {
+            break;
+        }
+
+        let samples = match load_samples(dir, &item.id) {
+            Ok(samples) => samples,
+            Err(e) => {
+                eprintln!("queue: dropping {}: {e}", item.id);
+                remove(dir, &item.id);
+                summary.failed += 1;
+                summary.remaining -= 1;
+                continue;
+            }
+        };
+
+        let progress = |chunk, total| println!("queue: {} chunk {chunk}/{total}", item.id);
+        let result =
+            chunk::transcribe_chunked(backend, item.meta.task, &samples, &progress, &|_| {}).await;
+        match result {
+            Ok(transcript) => {
+                let text = transcript.text.trim().to_string();
+                println!("queue: {} -> {}", item.id, text);
+                if !text.is_empty() {
+                    let entry = history::Entry {
+                        timestamp: item.meta.created,
+                        backend: transcript
+                            .fallback
+                            .clone()
+                            .unwrap_or_else(|| backend.name().to_string()),
+                        from_queue: true,
+                        transcript,
+                    };
+                    if let Err(e) = history::append(&entry) {
+                        eprintln!("history error: {e}");
+                    }
+                    if !idle() {
+                        println!(
+                            "queue: {} kept in history only; dictation in progress",
+                            item.id
+                        );
+                    } else if let Err(e) = paste::copy_text(&text) {
+                        eprintln!("clipboard error: {e}");
+                    }
+                }
+                remove(dir, &item.id);
+                summary.done += 1;
+                summary.remaining -= 1;
+            }
+            Err(e) => {
+                eprintln!("queue: {} failed: {e}", item.id);
+                item.meta.attempts += 1;
+                item.meta.last_error = e.to_string();
+                item.meta.parked = needs_attention(&e);
+                write_meta(dir, &item.id, &item.meta).ok();
+                summary.failed += 1;
+                if !item.meta.parked {
+                    break;
+                }
+            }
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enqueue_then_list_and_load() {
+        let tmp = std::env::temp_dir().join("whisper-paste-queue-test");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let samples = vec![0.1f32; 16000];
+        let err = TranscribeError::Network("connection refused".into());
+        enqueue_in(&tmp, &samples, Task::Translate, &err).unwrap();
+
+        let items = list_in(&tmp);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].meta.attempts, 1);
+        assert_eq!(items[0].meta.task, Task::Translate);
+        assert!((items[0].meta.duration_secs - 1.0).abs() < 1e-6);
+        assert!(items[0].meta.last_error.contains("connection refused"));
+
+        let loaded = load_samples(&tmp, &items[0].id).unwrap();
+        assert_eq!(loaded.len(), samples.len());
+
+        remove(&tmp, &items[0].id);
+        assert!(list_in(&tmp).is_empty());
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    /// Rejects every request as a bad request
+    struct BadRequest(std::sync::atomic::AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl TranscriptionBackend for BadRequest {
+        fn name(&self) -> &str {
+            "openai"
+        }
+
+        async fn transcribe(
+            &self,
+            _samples: &[f32],
+        ) -> Result<crate::transcribe::Transcript, TranscribeError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let message = "bad request".to_string();
+            Err(TranscribeError::from_status(400, None, message))
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_recordings_wait_for_a_manual_retry() {
+        let tmp = std::env::temp_dir().join("whisper-paste-queue-parked-test");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let err = TranscribeError::Network("connection refused".into());
+        enqueue_in(&tmp, &vec![0.1f32; 16000], Task::Transcribe, &err).unwrap();
+        assert!(!list_in(&tmp)[0].meta.parked);
+
+        let backend = BadRequest(Default::default());
+        let calls = || backend.0.load(std::sync::atomic::Ordering::SeqCst);
+        process_in(&tmp, &backend, false, &|| true).await;
+        assert_eq!(calls(), 1);
+        let items = list_in(&tmp);
+        assert!(items[0].meta.parked);
+        assert_eq!(items[0].meta.attempts, 2);
+
+        // The background retry leaves it alone from now on
+        process_in(&tmp, &backend, false, &|| true).await;
+        assert_eq!(calls(), 1);
+
+        process_in(&tmp, &backend, true, &|| true).await;
+        assert_eq!(calls(), 2);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn dictation_in_progress_pauses_the_queue() {
+        let tmp = std::env::temp_dir().join("whisper-paste-queue-busy-test");
+        std::fs::remove_dir_all(&tmp).ok();
+
+        let err = TranscribeError::Network("connection refused".into());
+        enqueue_in(&tmp, &vec![0.1f32; 16000], Task::Transcribe, &err).unwrap();
+        enqueue_in(&tmp, &vec![0.1f32; 16000], Task::Transcribe, &err).unwrap();
+        assert_eq!(list_in(&tmp).len(), 2);
+
+        // A dictation starts while the first recording is being sent
+        let checks = std::cell::Cell::new(0);
+        let idle = || {
+            checks.set(checks.get() + 1);
+            checks.get() == 1
+        };
+        let backend = BadRequest(Default::default());
+        process_in(&tmp, &backend, false, &idle).await;
+        assert_eq!(backend.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[test]
+    fn list_of_missing_dir_is_empty() {
+        let dir = std::env::temp_dir().join("whisper-paste-queue-missing");
+        assert!(list_in(&dir).is_empty());
+    }
+}
@@ -0,0 +1,670 @@
+//! OpenAI realtime transcription over a WebSocket. Audio is streamed while
+//! recording and committed at natural pauses, so most of a dictation is
+//! already transcribed by the time recording stops; only the last stretch
+//! is left to wait for.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::Engine;
+use reqwest::header;
+use serde::Deserialize;
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+
+use crate::audio::{self, FrameReceiver};
+use crate::config::Config;
+use crate::transcribe::{
+    api_error_message, LiveSession, Partial, RequestParams, Task, TranscribeError, Transcript,
+    TranscriptionBackend,
+};
+use crate::ws::{self, Message, WsReader, WsWriter};
+
+/// The API takes 24kHz mono pcm16
+const SESSION_RATE: u32 = 24000;
+/// Audio is sent in appends of about this length
+const APPEND_SECS: f32 = 0.1;
+/// The server refuses to commit less than 100ms
+const MIN_COMMIT_SAMPLES: usize = SESSION_RATE as usize / 10;
+/// How long to wait for outstanding transcripts after recording stops
+const FINISH_TIMEOUT: Duration = Duration::from_secs(15);
+
+type Upgraded = reqwest::Upgraded;
+
+struct Settings {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    headers: BTreeMap<String, String>,
+    params: RequestParams,
+}
+
+pub struct RealtimeBackend {
+    settings: Arc<Settings>,
+}
+
+impl RealtimeBackend {
+    pub fn new(cfg: &Config, client: reqwest::Client) -> Self {
+        Self {
+            settings: Arc::new(Settings {
+                client,
+                url: realtime_url(&cfg.base_url),
+                api_key: cfg.api_key.clone(),
+                model: cfg.model.clone(),
+                headers: cfg.headers.clone(),
+                params: RequestParams::from_config(cfg),
+            }),
+        }
+    }
+}
+
+/// `wss://` is spelled `https://` here: the upgrade goes through reqwest
+fn realtime_url(base_url: &str) -> String {
+    format!(
+        "{}/realtime?intent=transcription",
+        base_url.trim_end_matches('/')
+    )
+}
+
+#[async_trait]
+impl TranscriptionBackend for RealtimeBackend {
+    fn name(&self) -> &str {
+        "realtime"
+    }
+
+    /// A finished recording (queue retries, fallbacks) goes through a
+    /// session too, sent all at once
+    async fn transcribe(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.stream(Task::Transcribe, samples, &|_| {}).await
+    }
+
+    async fn stream(
+        &self,
+        task: Task,
+        samples: &[f32],
+        partial: Partial<'_>,
+    ) -> Result<Transcript, TranscribeError> {
+        if task == Task::Translate {
+            return self.translate(samples).await;
+        }
+        let (frames, rx) = mpsc::unbounded_channel();
+        frames.send((16000, samples.to_vec())).ok();
+        drop(frames);
+        run_session(&self.settings, rx, partial).await
+    }
+
+    fn live(&self, task: Task, partial: Arc<dyn Fn(&str) + Send + Sync>) -> Option<LiveSession> {
+        if task == Task::Translate {
+            return None;
+        }
+        let (frames, rx) = mpsc::unbounded_channel();
+        let settings = self.settings.clone();
+        Some(LiveSession {
+            frames,
+            result: Box::pin(async move { run_session(&settings, rx, &*partial).await }),
+        })
+    }
+}
+
+/// Stream `frames` until the sender is dropped, then wait for every
+/// committed stretch of audio to come back transcribed
+async fn run_session(
+    settings: &Settings,
+    mut frames: FrameReceiver,
+    partial: Partial<'_>,
+) -> Result<Transcript, TranscribeError> {
+    let (mut reader, writer) = connect(settings).await?;
+
+    // Frames are read on their own task so waiting for them can be
+    // cancelled by `select!` without losing half a frame
+    let (msg_tx, mut messages) = mpsc::unbounded_channel();
+    let reader_task = tokio::spawn(async move {
+        loop {
+            let msg = reader.recv().await;
+            let last = !matches!(msg, Ok(Some(_)));
+            if msg_tx.send(msg).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    let mut uplink = Uplink {
+        writer,
+        native: Vec::new(),
+        rate: SESSION_RATE,
+        resampler: None,
+        since_commit: Vec::new(),
+    };
+    let mut transcripts = Transcripts::default();
+    let result = async {
+        uplink.send(&session_update(settings)).await?;
+
+        let mut recording = true;
+        let finish_by = tokio::time::sleep(Duration::MAX);
+        tokio::pin!(finish_by);
+
+        while recording || !transcripts.is_done() {
+            tokio::select! {
+                frame = frames.recv(), if recording => match frame {
+                    Some((rate, samples)) => uplink.push(rate, &samples, &mut transcripts).await?,
+                    None => {
+                        recording = false;
+                        uplink.finish(&mut transcripts).await?;
+                        finish_by
+                            .as_mut()
+                            .reset(tokio::time::Instant::now() + FINISH_TIMEOUT);
+                    }
+                },
+                msg = messages.recv() => match msg {
+                    Some(Ok(Some(Message::Text(text)))) => {
+                        if transcripts.handle(&text)? {
+                            partial(&transcripts.text());
+                        }
+                    }
+                    Some(Ok(Some(Message::Ping(payload)))) => {
+                        uplink.writer.pong(&payload).await.map_err(network)?
+                    }
+                    Some(Ok(Some(Message::Binary(_)))) => {}
+                    Some(Err(e)) => return Err(network(e)),
+                    Some(Ok(None)) | None => {
+                        return Err(TranscribeError::Network(
+                            "realtime session closed by the server".into(),
+                        ))
+                    }
+                },
+                _ = &mut finish_by, if !recording => {
+                    return Err(TranscribeError::Network(
+                        "timed out waiting for the realtime transcript".into(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    uplink.writer.close().await.ok();
+    reader_task.abort();
+    result.map(|()| Transcript {
+        text: transcripts.text(),
+        ..Default::default()
+    })
+}
+
+async fn connect(
+    settings: &Settings,
+) -> Result<(WsReader<ReadHalf<Upgraded>>, WsWriter<WriteHalf<Upgraded>>), TranscribeError> {
+    let key = ws::new_key().map_err(network)?;
+    let mut req = settings
+        .client
+        .get(&settings.url)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_VERSION, "13")
+        .header(header::SEC_WEBSOCKET_KEY, &key)
+        .header("OpenAI-Beta", "realtime=v1");
+    if let Some(ref api_key) = settings.api_key {
+        req = req.bearer_auth(api_key);
+    }
+    for (name, value) in &settings.headers {
+        req = req.header(name, value);
+    }
+
+    let resp = req
+        .send()
+        .await
+        .map_err(|e| TranscribeError::Network(e.to_string()))?;
+    if resp.status() != reqwest::StatusCode::SWITCHING_PROTOCOLS {
+        let status = resp.status().as_u16();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(TranscribeError::from_status(
+            status,
+            None,
+            api_error_message(&body),
+        ));
+    }
+    let accepted = resp
+        .headers()
+        .get(header::SEC_WEBSOCKET_ACCEPT)
+        .is_some_and(|v| v.as_bytes() == ws::accept_key(&key).as_bytes());
+    if !accepted {
+        return Err(TranscribeError::Other(
+            "realtime server sent a bad WebSocket handshake".into(),
+        ));
+    }
+
+    let upgraded = resp
+        .upgrade()
+        .await
+        .map_err(|e| TranscribeError::Network(e.to_string()))?;
+    Ok(ws::split(upgraded, true))
+}
+
+fn session_update(settings: &Settings) -> serde_json::Value {
+    let mut transcription = serde_json::json!({ "model": settings.model });
+    if let Some(ref language) = settings.params.language {
+        transcription["language"] = language.clone().into();
+    }
+    if let Some(ref prompt) = settings.params.prompt {
+        transcription["prompt"] = prompt.clone().into();
+    }
+    serde_json::json!({
+        "type": "transcription_session.update",
+        "session": {
+            "input_audio_format": "pcm16",
+            "input_audio_transcription": transcription,
+            // We commit at pauses ourselves, so every commit is accounted for
+            "turn_detection": null,
+        }
+    })
+}
+
+fn network(e: std::io::Error) -> TranscribeError {
+    TranscribeError::Network(e.to_string())
+}
+
+/// The sending half: batches device frames into appends and commits the
+/// buffer at pauses
+struct Uplink {
+    writer: WsWriter<WriteHalf<Upgraded>>,
+    /// Device-rate audio not sent yet
+    native: Vec<f32>,
+    rate: u32,
+    /// Set while the device rate isn't the session's
+    resampler: Option<audio::Resampler>,
+    /// Session-rate audio sent since the last commit
+    since_commit: Vec<f32>,
+}
+
+impl Uplink {
+    async fn send(&mut self, event: &serde_json::Value) -> Result<(), TranscribeError> {
+        self.writer
+            .send_text(&event.to_string())
+            .await
+            .map_err(network)
+    }
+
+    async fn push(
+        &mut self,
+        rate: u32,
+        samples: &[f32],
+        transcripts: &mut Transcripts,
+    ) -> Result<(), TranscribeError> {
+        if rate != self.rate {
+            self.flush(true).await?;
+            self.rate = rate;
+            self.resampler =
+                (rate != SESSION_RATE).then(|| audio::Resampler::new(rate, SESSION_RATE));
+        }
+        self.native.extend_from_slice(samples);
+        if self.native.len() >= (rate as f32 * APPEND_SECS) as usize {
+            self.flush(false).await?;
+            if audio::find_pause_cut(&self.since_commit, SESSION_RATE).is_some() {
+                self.commit(transcripts).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the audio gathered so far. At the `end` of the stream, or of a
+    /// device rate, that includes what the resampler was holding back.
+    async fn flush(&mut self, end: bool) -> Result<(), TranscribeError> {
+        let native = std::mem::take(&mut self.native);
+        let pcm = match self.resampler {
+            Some(ref mut resampler) => {
+                let mut pcm = resampler.process(&native);
+                if end {
+                    pcm.extend(resampler.finish());
+                }
+                pcm
+            }
+            None => native,
+        };
+        if pcm.is_empty() {
+            return Ok(());
+        }
+
+        let bytes: Vec<u8> = pcm
+            .iter()
+            .flat_map(|&s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        self.since_commit.extend_from_slice(&pcm);
+        self.send(&serde_json::json!({
+            "type": "input_audio_buffer.append",
+            "audio": base64::engine::general_purpose::STANDARD.encode(bytes),
+        }))
+        .await
+    }
+
+    /// Commit the buffer for transcription, or drop it if nobody spoke:
+    /// silence is billed all the same and invites made-up text
+    async fn commit(&mut self, transcripts: &mut Transcripts) -> Result<(), TranscribeError> {
+        if audio::speech_bounds(&self.since_commit, 0.0).is_none() {
+            self.send(&serde_json::json!({ "type": "input_audio_buffer.clear" }))
+                .await?;
+            self.since_commit.clear();
+            return Ok(());
+        }
+        self.send(&serde_json::json!({ "type": "input_audio_buffer.commit" }))
+            .await?;
+        self.since_commit.clear();
+        transcripts.commits_sent += 1;
+        Ok(())
+    }
+
+    /// Send what is left and commit it, unless it is too short to count
+    async fn finish(&mut self, transcripts: &mut Transcripts) -> Result<(), TranscribeError> {
+        self.flush(true).await?;
+        if self.since_commit.len() >= MIN_COMMIT_SAMPLES {
+            self.commit(transcripts).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Event {
+    #[serde(rename = "input_audio_buffer.committed")]
+    Committed { item_id: String },
+    #[serde(rename = "conversation.item.input_audio_transcription.delta")]
+    Delta { item_id: String, delta: String },
+    #[serde(rename = "conversation.item.input_audio_transcription.completed")]
+    Completed { item_id: String, transcript: String },
+    #[serde(rename = "conversation.item.input_audio_transcription.failed")]
+    Failed { error: ApiError },
+    #[serde(rename = "error")]
+    Error { error: ApiError },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    code: Option<String>,
+    message: String,
+}
+
+/// Transcripts of the committed stretches of audio, in commit order
+#[derive(Default)]
+struct Transcripts {
+    items: Vec<String>,
+    texts: HashMap<String, String>,
+    completed: HashSet<String>,
+    commits_sent: usize,
+    commits_answered: usize,
+}
+
+impl Transcripts {
+    /// Apply one server event. Returns whether the text changed.
+    fn handle(&mut self, json: &str) -> Result<bool, TranscribeError> {
+        let Ok(event) = serde_json::from_str::<Event>(json) else {
+            return Ok(false);
+        };
+        match event {
+            Event::Committed { item_id } => {
+                self.commits_answered += 1;
+                self.track(&item_id);
+                Ok(false)
+            }
+            Event::Delta { item_id, delta } => {
+                self.track(&item_id);
+                self.texts.entry(item_id).or_default().push_str(&delta);
+                Ok(true)
+            }
+            Event::Completed {
+                item_id,
+                transcript,
+            } => {
+                self.track(&item_id);
+                self.texts.insert(item_id.clone(), transcript);
+                self.completed.insert(item_id);
+                Ok(true)
+            }
+            // Nothing new was buffered since the last commit
+            Event::Error { error }
+                if error.code.as_deref() == Some("input_audio_buffer_commit_empty") =>
+            {
+                self.commits_answered += 1;
+                Ok(false)
+            }
+            Event::Failed { error } | Event::Error { error } => Err(TranscribeError::Other(
+                format!("realtime: {}", error.message),
+            )),
+            Event::Other => Ok(false),
+        }
+    }
+
+    fn track(&mut self, item_id: &str) {
+        if !self.items.iter().any(|id| id == item_id) {
+            self.items.push(item_id.to_string());
+        }
+    }
+
+    /// Every commit acknowledged and every committed item transcribed
+    fn is_done(&self) -> bool {
+        self.commits_answered >= self.commits_sent
+            && self.items.iter().all(|id| self.completed.contains(id))
+    }
+
+    fn text(&self) -> String {
+        self.items
+            .iter()
+            .filter_map(|id| self.texts.get(id))
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A local stand-in for the realtime endpoint. Each commit is answered
+    /// with a delta and a transcript saying how much audio it covered.
+    async fn stand_in_server(reject: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut key = String::new();
+            loop {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("sec-websocket-key") {
+                        key = value.trim().to_string();
+                    }
+                }
+            }
+
+            let mut stream = stream.into_inner();
+            if reject {
+                let body = r#"{"error": {"message": "Incorrect API key provided"}}"#;
+                let resp = format!(
+                    "HTTP/1.1 401 Unauthorized\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(resp.as_bytes()).await.unwrap();
+                return;
+            }
+            let resp = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: Upgrade\r\nsec-websocket-accept: {}\r\n\r\n",
+                ws::accept_key(&key)
+            );
+            stream.write_all(resp.as_bytes()).await.unwrap();
+
+            let (mut rx, mut tx) = ws::split(stream, false);
+            let mut buffered = 0usize;
+            let mut items = 0;
+            while let Ok(Some(Message::Text(text))) = rx.recv().await {
+                let event: serde_json::Value = serde_json::from_str(&text).unwrap();
+                match event["type"].as_str().unwrap() {
+                    "input_audio_buffer.append" => {
+                        let audio = event["audio"].as_str().unwrap();
+                        let bytes = base64::engine::general_purpose::STANDARD
+                            .decode(audio)
+                            .unwrap();
+                        buffered += bytes.len() / 2;
+                    }
+                    "input_audio_buffer.clear" => buffered = 0,
+                    "input_audio_buffer.commit" => {
+                        items += 1;
+                        let item_id = format!("item_{items}");
+                        let secs = buffered as f32 / SESSION_RATE as f32;
+                        buffered = 0;
+                        for reply in [
+                            serde_json::json!({"type": "input_audio_buffer.committed", "item_id": item_id}),
+                            serde_json::json!({"type": "conversation.item.input_audio_transcription.delta", "item_id": item_id, "delta": "heard"}),
+                            serde_json::json!({"type": "conversation.item.input_audio_transcription.completed", "item_id": item_id, "transcript": format!("heard {secs:.1}s")}),
+                        ] {
+                            tx.send_text(&reply.to_string()).await.unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        format!("http://{addr}/v1")
+    }
+
+    fn backend(base_url: &str) -> RealtimeBackend {
+        RealtimeBackend {
+            settings: Arc::new(Settings {
+                client: reqwest::Client::new(),
+                url: realtime_url(base_url),
+                api_key: Some("sk-test".into()),
+                model: "gpt-4o-transcribe".into(),
+                headers: BTreeMap::new(),
+                params: RequestParams::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn translation_is_refused_up_front() {
+        let realtime: Arc<dyn TranscriptionBackend> = Arc::new(backend("http://127.0.0.1:1/v1"));
+        assert!(!realtime.can_translate());
+        assert!(realtime.live(Task::Translate, Arc::new(|_| {})).is_none());
+    }
+
+    fn tone(secs: f32, rate: u32) -> Vec<f32> {
+        (0..(secs * rate as f32) as usize)
+            .map(|i| 0.3 * (i as f32 * 0.05).sin())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn whole_recording_through_a_session() {
+        let base_url = stand_in_server(false).await;
+        let transcript = backend(&base_url)
+            .transcribe(&tone(2.0, 16000))
+            .await
+            .unwrap();
+        assert_eq!(transcript.text, "heard 2.0s");
+    }
+
+    #[tokio::test]
+    async fn live_session_commits_at_pauses() {
+        let base_url = stand_in_server(false).await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_partial = seen.clone();
+        let session = backend(&base_url)
+            .live(
+                Task::Transcribe,
+                Arc::new(move |text: &str| seen_partial.lock().unwrap().push(text.to_string())),
+            )
+            .unwrap();
+        let result = tokio::spawn(session.result);
+
+        // 9s of speech, a 1s pause, 3s more, in 10ms device callbacks
+        let mut audio = tone(9.0, 48000);
+        audio.extend(vec![0.0; 48000]);
+        audio.extend(tone(3.0, 48000));
+        for frame in audio.chunks(480) {
+            session.frames.send((48000, frame.to_vec())).unwrap();
+        }
+        drop(session.frames);
+
+        let transcript = result.await.unwrap().unwrap();
+        let secs: Vec<f32> = transcript
+            .text
+            .split_whitespace()
+            .filter_map(|w| w.strip_suffix('s')?.parse().ok())
+            .collect();
+        assert_eq!(secs.len(), 2, "{}", transcript.text);
+        assert!((secs.iter().sum::<f32>() - 13.0).abs() < 0.15);
+        assert!(seen.lock().unwrap().iter().any(|t| t == "heard"));
+    }
+
+    #[tokio::test]
+    async fn silence_is_not_committed() {
+        let base_url = stand_in_server(false).await;
+        let transcript = backend(&base_url)
+            .transcribe(&vec![0.0; 32000])
+            .await
+            .unwrap();
+        assert_eq!(transcript.text, "");
+
+        // A long silence before speaking is dropped at the first pause cut
+        let base_url = stand_in_server(false).await;
+        let session = backend(&base_url)
+            .live(Task::Transcribe, Arc::new(|_| {}))
+            .unwrap();
+        let result = tokio::spawn(session.result);
+        let mut audio = vec![0.0; 48000 * 9];
+        audio.extend(tone(2.0, 48000));
+        for frame in audio.chunks(480) {
+            session.frames.send((48000, frame.to_vec())).unwrap();
+        }
+        drop(session.frames);
+
+        let transcript = result.await.unwrap().unwrap();
+        let secs: f32 = transcript
+            .text
+            .strip_prefix("heard ")
+            .and_then(|t| t.strip_suffix('s')?.parse().ok())
+            .unwrap();
+        assert!((2.0..3.0).contains(&secs), "{}", transcript.text);
+    }
+
+    #[tokio::test]
+    async fn rejected_handshake_is_an_auth_error() {
+        let base_url = stand_in_server(true).await;
+        match backend(&base_url).transcribe(&tone(1.0, 16000)).await {
+            Err(TranscribeError::Auth(msg)) => assert_eq!(msg, "Incorrect API key provided"),
+            other => panic!("expected auth error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn empty_commit_counts_as_answered() {
+        let mut transcripts = Transcripts {
+            commits_sent: 1,
+            ..Default::default()
+        };
+        assert!(!transcripts.is_done());
+        let empty = r#"{"type":"error","error":{"code":"input_audio_buffer_commit_empty","message":"buffer too small"}}"#;
+        assert!(!transcripts.handle(empty).unwrap());
+        assert!(transcripts.is_done());
+
+        let other = r#"{"type":"error","error":{"message":"invalid model"}}"#;
+        assert!(transcripts.handle(other).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+//! Minimal server-sent events decoder for streamed transcription responses.
+//! Only `data:` fields matter to us; event names, ids and comments are
+//! skipped.
+
+/// Feeds raw response chunks in, yields the data of each complete event
+#[derive(Default)]
+pub struct SseDecoder {
+    /// Bytes after the last newline, which may end mid-character
+    pending: Vec<u8>,
+    /// `data:` lines of the event being read
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // A blank line dispatches the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data
+                    .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b"data: {\"a\":").is_empty());
+        assert_eq!(decoder.push(b"1}\r\n\r\ndata: two\n"), vec!["{\"a\":1}"]);
+        assert_eq!(decoder.push(b"\n"), vec!["two"]);
+    }
+
+    #[test]
+    fn multi_line_data_and_other_fields() {
+        let mut decoder = SseDecoder::default();
+        let events = decoder.push(b": keep-alive\nevent: delta\nid: 7\ndata: one\ndata:two\n\n");
+        assert_eq!(events, vec!["one\ntwo"]);
+    }
+
+    #[test]
+    fn utf8_split_mid_character() {
+        let mut decoder = SseDecoder::default();
+        let bytes = "data: Grüße\n\n".as_bytes();
+        assert!(decoder.push(&bytes[..9]).is_empty());
+        assert_eq!(decoder.push(&bytes[9..]), vec!["Grüße"]);
+    }
+}
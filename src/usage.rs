@@ -0,0 +1,471 @@
+//! Audio sent to paid APIs, kept as JSON lines in `usage.jsonl` so spend can
+//! be reported per month and capped by `monthly_budget`. Costs are estimates
+//! from per-minute prices; months are calendar months in UTC.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::config::{Budget, OverBudget};
+use crate::transcribe::{
+    LiveSession, Partial, Task, TranscribeError, Transcript, TranscriptionBackend,
+};
+
+/// Built-in USD per minute of audio for the hosted OpenAI models; `[prices]`
+/// in the config adds to and overrides these
+pub const DEFAULT_PRICES: &[(&str, f64)] = &[
+    ("whisper-1", 0.006),
+    ("gpt-4o-transcribe", 0.006),
+    ("gpt-4o-mini-transcribe", 0.003),
+];
+
+/// One successful request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    /// Unix seconds when the request finished
+    pub timestamp: u64,
+    pub backend: String,
+    /// Model, or deployment name for Azure
+    pub model: String,
+    /// Audio seconds sent, chunk overlaps included
+    pub seconds: f32,
+}
+
+pub fn ledger_path() -> PathBuf {
+    crate::config::data_dir().join("usage.jsonl")
+}
+
+fn append_to(path: &Path, record: &Record) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("failed to create data dir: {e}"))?;
+    }
+
+    let line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open usage ledger: {e}"))?;
+    writeln!(file, "{line}").map_err(|e| format!("failed to write usage ledger: {e}"))
+}
+
+/// Every record in the ledger; unreadable lines are skipped
+pub fn load() -> Vec<Record> {
+    load_from(&ledger_path())
+}
+
+fn load_from(path: &Path) -> Vec<Record> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Month-to-date figures for one backend and model
+#[derive(Debug, Default, PartialEq)]
+pub struct Totals {
+    pub requests: usize,
+    pub seconds: f64,
+    /// `None` when no price is configured for the model
+    pub cost: Option<f64>,
+}
+
+/// Totals per (backend, model) for the month containing `now`
+pub fn month_totals(
+    records: &[Record],
+    prices: &BTreeMap<String, f64>,
+    now: u64,
+) -> BTreeMap<(String, String), Totals> {
+    let month = year_month(now);
+    let mut totals: BTreeMap<(String, String), Totals> = BTreeMap::new();
+    for record in records.iter().filter(|r| year_month(r.timestamp) == month) {
+        let entry = totals
+            .entry((record.backend.clone(), record.model.clone()))
+            .or_default();
+        entry.requests += 1;
+        entry.seconds += record.seconds as f64;
+    }
+    for ((_, model), entry) in totals.iter_mut() {
+        entry.cost = prices
+            .get(model)
+            .map(|per_min| per_min * entry.seconds / 60.0);
+    }
+    totals
+}
+
+/// Estimated spend so far this month
+pub fn month_cost(records: &[Record], prices: &BTreeMap<String, f64>, now: u64) -> f64 {
+    month_totals(records, prices, now)
+        .values()
+        .filter_map(|t| t.cost)
+        .sum()
+}
+
+/// Why remote transcription is refused right now, if it is
+pub fn over_budget(budget: &Budget, prices: &BTreeMap<String, f64>) -> Option<TranscribeError> {
+    over_budget_in(&ledger_path(), budget, prices)
+}
+
+fn over_budget_in(
+    ledger: &Path,
+    budget: &Budget,
+    prices: &BTreeMap<String, f64>,
+) -> Option<TranscribeError> {
+    let spent = month_cost(&load_from(ledger), prices, crate::history::now_unix());
+    (spent >= budget.monthly).then_some(TranscribeError::BudgetExceeded {
+        spent,
+        budget: budget.monthly,
+    })
+}
+
+/// (year, month) of a Unix timestamp in UTC
+pub fn year_month(unix: u64) -> (i64, u32) {
+    // Days to civil date, after Howard Hinnant's `civil_from_days`
+    let z = (unix / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32)
+}
+
+/// Records the audio of every successful request to a paid backend
+pub struct Metered {
+    inner: Arc<dyn TranscriptionBackend>,
+    model: String,
+    ledger: PathBuf,
+}
+
+impl Metered {
+    pub fn new(inner: Arc<dyn TranscriptionBackend>, model: String) -> Self {
+        Self {
+            inner,
+            model,
+            ledger: ledger_path(),
+        }
+    }
+
+    fn record(&self, seconds: f32) {
+        let record = Record {
+            timestamp: crate::history::now_unix(),
+            backend: self.inner.name().to_string(),
+            model: self.model.clone(),
+            seconds,
+        };
+        if let Err(e) = append_to(&self.ledger, &record) {
+            eprintln!("usage error: {e}");
+        }
+    }
+
+    fn metered(
+        &self,
+        result: Result<Transcript, TranscribeError>,
+        samples: &[f32],
+    ) -> Result<Transcript, TranscribeError> {
+        if result.is_ok() {
+            self.record(samples.len() as f32 / 16000.0);
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl TranscriptionBackend for Metered {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn can_translate(&self) -> bool {
+        self.inner.can_translate()
+    }
+
+    async fn transcribe(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        let result = self.inner.transcribe(samples).await;
+        self.metered(result, samples)
+    }
+
+    async fn translate(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        let result = self.inner.translate(samples).await;
+        self.metered(result, samples)
+    }
+
+    async fn stream(
+        &self,
+        task: Task,
+        samples: &[f32],
+        partial: Partial<'_>,
+    ) -> Result<Transcript, TranscribeError> {
+        let result = self.inner.stream(task, samples, partial).await;
+        self.metered(result, samples)
+    }
+
+    fn max_samples(&self) -> Option<usize> {
+        self.inner.max_samples()
+    }
+
+    /// Frames pass through a counter on their way to the inner session
+    fn live(&self, task: Task, partial: Arc<dyn Fn(&str) + Send + Sync>) -> Option<LiveSession> {
+        let inner = self.inner.live(task, partial)?;
+        let (frames, mut counted) = mpsc::unbounded_channel::<(u32, Vec<f32>)>();
+        let metered = Metered {
+            inner: self.inner.clone(),
+            model: self.model.clone(),
+            ledger: self.ledger.clone(),
+        };
+
+        let result = async move {
+            let inner_frames = inner.frames;
+            let forward = async move {
+                let mut seconds = 0.0;
+                while let Some((rate, frame)) = counted.recv().await {
+                    seconds += frame.len() as f32 / rate as f32;
+                    inner_frames.send((rate, frame)).ok();
+                }
+                seconds
+            };
+            let (seconds, result) = tokio::join!(forward, inner.result);
+            if result.is_ok() {
+                metered.record(seconds);
+            }
+            result
+        };
+        Some(LiveSession {
+            frames,
+            result: Box::pin(result),
+        })
+    }
+
+    async fn warm_up(&self) {
+        self.inner.warm_up().await;
+    }
+}
+
+/// Enforces `monthly_budget` on one paid backend: past it, its requests
+/// are refused (so a fallback chain moves on) or go to the local backend
+pub struct Budgeted {
+    inner: Arc<dyn TranscriptionBackend>,
+    /// Required when `budget.over` is `OverBudget::Local`
+    local: Option<Arc<dyn TranscriptionBackend>>,
+    budget: Budget,
+    prices: BTreeMap<String, f64>,
+    ledger: PathBuf,
+}
+
+impl Budgeted {
+    pub fn new(
+        inner: Arc<dyn TranscriptionBackend>,
+        local: Option<Arc<dyn TranscriptionBackend>>,
+        budget: Budget,
+        prices: BTreeMap<String, f64>,
+    ) -> Result<Self, String> {
+        if budget.over == OverBudget::Local && local.is_none() {
+            return Err(
+                "over_budget = \"local\" needs a local backend, at the top level or in [[fallback]]"
+                    .into(),
+            );
+        }
+        Ok(Self {
+            inner,
+            local,
+            budget,
+            prices,
+            ledger: ledger_path(),
+        })
+    }
+
+    #[cfg(test)]
+    fn with_ledger(mut self, ledger: PathBuf) -> Self {
+        self.ledger = ledger;
+        self
+    }
+
+    /// Where requests go right now: `Err` if refused, `Ok(true)` for local
+    fn route(&self) -> Result<bool, TranscribeError> {
+        match over_budget_in(&self.ledger, &self.budget, &self.prices) {
+            None => Ok(false),
+            Some(_) if self.budget.over == OverBudget::Local => Ok(true),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptionBackend for Budgeted {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn can_translate(&self) -> bool {
+        self.inner.can_translate()
+    }
+
+    async fn transcribe(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.stream(Task::Transcribe, samples, &|_| {}).await
+    }
+
+    async fn translate(&self, samples: &[f32]) -> Result<Transcript, TranscribeError> {
+        self.stream(Task::Translate, samples, &|_| {}).await
+    }
+
+    async fn stream(
+        &self,
+        task: Task,
+        samples: &[f32],
+        partial: Partial<'_>,
+    ) -> Result<Transcript, TranscribeError> {
+        match (self.route()?, &self.local) {
+            (true, Some(local)) => {
+                let mut transcript = local.stream(task, samples, partial).await?;
+                transcript.fallback = Some(format!("{} (budget)", local.name()));
+                Ok(transcript)
+            }
+            _ => self.inner.stream(task, samples, partial).await,
+        }
+    }
+
+    fn max_samples(&self) -> Option<usize> {
+        self.inner.max_samples()
+    }
+
+    fn live(&self, task: Task, partial: Arc<dyn Fn(&str) + Send + Sync>) -> Option<LiveSession> {
+        match self.route() {
+            Ok(false) => self.inner.live(task, partial),
+            _ => None,
+        }
+    }
+
+    async fn warm_up(&self) {
+        if let Ok(false) = self.route() {
+            self.inner.warm_up().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::fallback::FallbackBackend;
+
+    /// Answers with its name and counts requests
+    struct Named(&'static str, AtomicUsize);
+
+    #[async_trait]
+    impl TranscriptionBackend for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn transcribe(&self, _samples: &[f32]) -> Result<Transcript, TranscribeError> {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(Transcript {
+                text: self.0.to_string(),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn months_in_utc() {
+        assert_eq!(year_month(0), (1970, 1));
+        // 2024-02-29 23:59:59 and a second later
+        assert_eq!(year_month(1_709_251_199), (2024, 2));
+        assert_eq!(year_month(1_709_251_200), (2024, 3));
+        // 2025-12-31 23:59:59 and a second later
+        assert_eq!(year_month(1_767_225_599), (2025, 12));
+        assert_eq!(year_month(1_767_225_600), (2026, 1));
+    }
+
+    #[test]
+    fn ledger_totals_this_month_only() {
+        let tmp = std::env::temp_dir().join("whisper-paste-usage-test");
+        std::fs::remove_dir_all(&tmp).ok();
+        let path = tmp.join("usage.jsonl");
+
+        let march = 1_709_251_200;
+        let record = |timestamp, backend: &str, model: &str, seconds| Record {
+            timestamp,
+            backend: backend.to_string(),
+            model: model.to_string(),
+            seconds,
+        };
+        for r in [
+            record(march - 10, "openai", "whisper-1", 600.0),
+            record(march + 10, "openai", "whisper-1", 90.0),
+            record(march + 20, "openai", "whisper-1", 30.0),
+            record(march + 30, "azure", "my-deployment", 60.0),
+        ] {
+            append_to(&path, &r).unwrap();
+        }
+
+        let prices: BTreeMap<String, f64> = DEFAULT_PRICES
+            .iter()
+            .map(|&(model, price)| (model.to_string(), price))
+            .collect();
+        let records = load_from(&path);
+        let totals = month_totals(&records, &prices, march + 100);
+
+        let openai = &totals[&("openai".to_string(), "whisper-1".to_string())];
+        assert_eq!(openai.requests, 2);
+        assert_eq!(openai.seconds, 120.0);
+        assert!((openai.cost.unwrap() - 0.012).abs() < 1e-9);
+        assert_eq!(
+            totals[&("azure".to_string(), "my-deployment".to_string())].cost,
+            None
+        );
+        assert!((month_cost(&records, &prices, march + 100) - 0.012).abs() < 1e-9);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn spent_budget_leaves_free_fallbacks_running() {
+        let tmp = std::env::temp_dir().join("whisper-paste-budget-test");
+        std::fs::remove_dir_all(&tmp).ok();
+        let ledger = tmp.join("usage.jsonl");
+        let spent = Record {
+            timestamp: crate::history::now_unix(),
+            backend: "openai".to_string(),
+            model: "whisper-1".to_string(),
+            // $0.60 at the built-in price
+            seconds: 6000.0,
+        };
+        append_to(&ledger, &spent).unwrap();
+
+        let prices: BTreeMap<String, f64> = DEFAULT_PRICES
+            .iter()
+            .map(|&(model, price)| (model.to_string(), price))
+            .collect();
+        let budget = Budget {
+            monthly: 0.5,
+            over: OverBudget::Refuse,
+        };
+        let paid = Arc::new(Named("openai", AtomicUsize::new(0)));
+        let local = Arc::new(Named("local", AtomicUsize::new(0)));
+        let budgeted = Budgeted::new(paid.clone(), None, budget, prices)
+            .unwrap()
+            .with_ledger(ledger);
+        let chain = FallbackBackend::new(vec![
+            ("openai".to_string(), Arc::new(budgeted)),
+            ("local".to_string(), local.clone()),
+        ]);
+
+        let transcript = chain.transcribe(&[0.0; 16000]).await.unwrap();
+        assert_eq!(transcript.text, "local");
+        assert_eq!(transcript.fallback.as_deref(), Some("local"));
+        assert_eq!(paid.1.load(Ordering::SeqCst), 0);
+        assert_eq!(local.1.load(Ordering::SeqCst), 1);
+
+        std::fs::remove_dir_all(&tmp).ok();
+    }
+}
@@ -0,0 +1,275 @@
+//! Just enough RFC 6455 WebSocket framing for the realtime backend. The
+//! HTTP upgrade itself goes through reqwest (so TLS is shared with the other
+//! backends); this only reads and writes frames on the upgraded stream.
+//!
+//! Reading and writing are split so a session can wait on incoming messages
+//! and outgoing audio at the same time without losing half-read frames.
+
+use std::io;
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Larger messages are refused rather than buffered
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Must be answered with `WsWriter::pong`
+    Ping(Vec<u8>),
+}
+
+/// Random `Sec-WebSocket-Key` for the upgrade request
+pub fn new_key() -> io::Result<String> {
+    let bytes: [u8; 16] = random_bytes()?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// The `Sec-WebSocket-Accept` a server must answer `key` with
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(ACCEPT_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha.finalize())
+}
+
+/// Split an upgraded connection. Clients mask what they send; servers
+/// (only the test stand-ins) don't.
+pub fn split<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    client: bool,
+) -> (WsReader<ReadHalf<S>>, WsWriter<WriteHalf<S>>) {
+    let (read, write) = tokio::io::split(stream);
+    (
+        WsReader {
+            stream: read,
+            fragments: Vec::new(),
+            fragment_op: None,
+        },
+        WsWriter {
+            stream: write,
+            masked: client,
+        },
+    )
+}
+
+pub struct WsReader<R> {
+    stream: R,
+    fragments: Vec<u8>,
+    fragment_op: Option<u8>,
+}
+
+impl<R: AsyncRead + Unpin> WsReader<R> {
+    /// Next message, or `None` once the peer closes the connection
+    pub async fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            let Some((fin, op, payload)) = self.read_frame().await? else {
+                return Ok(None);
+            };
+
+            match op {
+                OP_CLOSE => return Ok(None),
+                OP_PING => return Ok(Some(Message::Ping(payload))),
+                OP_PONG => continue,
+                OP_TEXT | OP_BINARY if self.fragment_op.is_none() => {
+                    if fin {
+                        return to_message(op, payload).map(Some);
+                    }
+                    self.fragment_op = Some(op);
+                    self.fragments = payload;
+                }
+                OP_CONTINUATION if self.fragment_op.is_some() => {
+                    if self.fragments.len() + payload.len() > MAX_MESSAGE_BYTES {
+                        return Err(invalid("websocket message too large"));
+                    }
+                    self.fragments.extend_from_slice(&payload);
+                    if fin {
+                        let op = self.fragment_op.take().unwrap_or(OP_BINARY);
+                        return to_message(op, std::mem::take(&mut self.fragments)).map(Some);
+                    }
+                }
+                _ => return Err(invalid("unexpected websocket frame")),
+            }
+        }
+    }
+
+    /// (fin, opcode, unmasked payload), or `None` at end of stream
+    async fn read_frame(&mut self) -> io::Result<Option<(bool, u8, Vec<u8>)>> {
+        let mut head = [0u8; 2];
+        match self.stream.read_exact(&mut head).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let fin = head[0] & 0x80 != 0;
+        let op = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7F {
+            126 => self.stream.read_u16().await? as u64,
+            127 => self.stream.read_u64().await?,
+            n => n as u64,
+        };
+        if len > MAX_MESSAGE_BYTES as u64 {
+            return Err(invalid("websocket frame too large"));
+        }
+
+        let mut mask = [0u8; 4];
+        if masked {
+            self.stream.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.stream.read_exact(&mut payload).await?;
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+
+        Ok(Some((fin, op, payload)))
+    }
+}
+
+pub struct WsWriter<W> {
+    stream: W,
+    /// Set for the client side, which must mask every frame
+    masked: bool,
+}
+
+impl<W: AsyncWrite + Unpin> WsWriter<W> {
+    pub async fn send_text(&mut self, text: &str) -> io::Result<()> {
+        self.write_frame(OP_TEXT, text.as_bytes()).await
+    }
+
+    pub async fn pong(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.write_frame(OP_PONG, payload).await
+    }
+
+    /// Send a close frame; the connection is done after this
+    pub async fn close(&mut self) -> io::Result<()> {
+        // 1000: normal closure
+        self.write_frame(OP_CLOSE, &1000u16.to_be_bytes()).await?;
+        self.stream.shutdown().await
+    }
+
+    async fn write_frame(&mut self, op: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | op);
+
+        let mask_bit = if self.masked { 0x80 } else { 0 };
+        match payload.len() {
+            n @ 0..=125 => frame.push(mask_bit | n as u8),
+            n @ 126..=0xFFFF => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+
+        let start = frame.len();
+        if self.masked {
+            let mask = random_bytes()?;
+            frame.extend_from_slice(&mask);
+            frame.extend_from_slice(payload);
+            apply_mask(&mut frame[start + 4..], mask);
+        } else {
+            frame.extend_from_slice(payload);
+        }
+
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await
+    }
+}
+
+fn to_message(op: u8, payload: Vec<u8>) -> io::Result<Message> {
+    if op == OP_TEXT {
+        String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| invalid("websocket text is not UTF-8"))
+    } else {
+        Ok(Message::Binary(payload))
+    }
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Bytes from the operating system's secure random source. RFC 6455 needs
+/// masking keys a proxy can't predict, which rules out a seeded PRNG.
+fn random_bytes<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::getrandom(&mut bytes).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(new_key().unwrap().len(), 24);
+        assert_ne!(new_key().unwrap(), new_key().unwrap());
+    }
+
+    #[tokio::test]
+    async fn masked_frames_round_trip() {
+        let (client, server) = tokio::io::duplex(1 << 20);
+        let (_, mut client_tx) = split(client, true);
+        let (mut server_rx, _) = split(server, false);
+
+        for len in [0, 125, 126, 70_000] {
+            let text = "x".repeat(len);
+            client_tx.send_text(&text).await.unwrap();
+            assert_eq!(server_rx.recv().await.unwrap(), Some(Message::Text(text)));
+        }
+
+        client_tx.close().await.unwrap();
+        assert_eq!(server_rx.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn fragments_are_joined_and_pings_surface() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let (mut client_rx, _) = split(client, true);
+
+        // "Hel" + "lo" as two unmasked server frames, then a ping
+        server
+            .write_all(&[
+                0x01, 3, b'H', b'e', b'l', 0x80, 2, b'l', b'o', 0x89, 1, b'!',
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            client_rx.recv().await.unwrap(),
+            Some(Message::Text("Hello".into()))
+        );
+        assert_eq!(
+            client_rx.recv().await.unwrap(),
+            Some(Message::Ping(b"!".to_vec()))
+        );
+    }
+}